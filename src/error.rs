@@ -2,7 +2,7 @@
 
 use std::path::PathBuf;
 
-use axum::http::StatusCode;
+use axum::http::{header, HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use serde_json::json;
 use thiserror::Error;
@@ -53,6 +53,21 @@ pub enum ConfigError {
 
     #[error("Secrets directory not found: {0}")]
     SecretsDirectoryNotFound(PathBuf),
+
+    #[error("Secret command failed: {0}")]
+    SecretCommand(String),
+
+    #[error("Keyring error: {0}")]
+    Keyring(String),
+
+    #[error("Secret file {0} is accessible by group/other (mode {1:o}), should be 600")]
+    InsecureSecretPermissions(PathBuf, u32),
+
+    #[error("Service '{0}' uses secret '{1}', which was not found")]
+    MissingServiceSecret(String, String),
+
+    #[error("Secret bundle error: {0}")]
+    Bundle(String),
 }
 
 /// Proxy-related errors
@@ -67,26 +82,58 @@ pub enum ProxyError {
     #[error("Upstream unavailable: {0}")]
     UpstreamUnavailable(String),
 
+    #[error("Could not resolve upstream host '{0}' (check the service's upstream for a typo): {1}")]
+    UpstreamDns(String, String),
+
+    #[error("Upstream TLS error (check the upstream certificate, TLS-intercepting proxies and the system clock): {0}")]
+    UpstreamTls(String),
+
     #[error("Upstream timeout: {0}")]
     UpstreamTimeout(String),
 
     #[error("Upstream request failed: {0}")]
     UpstreamRequest(String),
 
+    #[error("Upstream response too large: {0}")]
+    UpstreamResponseTooLarge(String),
+
     #[error("Invalid token format: {0}")]
     InvalidToken(String),
 
+    #[error("Secret not available: {0}")]
+    SecretUnavailable(String),
+
+    #[error("Mock response unavailable: {0}")]
+    MockResponse(String),
+
     #[error("Bad request: {0}")]
     BadRequest(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("Too many requests (retry after {retry_after:?}s)")]
+    TooManyRequests { retry_after: Option<u64> },
 }
 
 impl ProxyError {
     /// Classify a reqwest error into the appropriate ProxyError variant.
+    /// The URL is dropped from the message, since its query may hold a credential.
     pub fn from_reqwest(err: reqwest::Error) -> Self {
-        if err.is_timeout() {
-            ProxyError::UpstreamTimeout(err.to_string())
+        let host = err.url().and_then(|url| url.host_str()).map(str::to_string);
+        let err = err.without_url();
+        // A connect timeout is also a timeout, but means the upstream is unreachable
+        if err.is_connect() && is_dns_error(&err) {
+            ProxyError::UpstreamDns(host.unwrap_or_default(), err.to_string())
+        } else if err.is_connect() && is_tls_error(&err) {
+            ProxyError::UpstreamTls(err.to_string())
         } else if err.is_connect() {
             ProxyError::UpstreamUnavailable(err.to_string())
+        } else if err.is_timeout() {
+            ProxyError::UpstreamTimeout(err.to_string())
         } else {
             ProxyError::UpstreamRequest(err.to_string())
         }
@@ -96,10 +143,18 @@ impl ProxyError {
         match self {
             ProxyError::UnknownService(_) => StatusCode::NOT_FOUND,
             ProxyError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ProxyError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ProxyError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ProxyError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
             ProxyError::UpstreamUnavailable(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::UpstreamDns(..) => StatusCode::BAD_GATEWAY,
+            ProxyError::UpstreamTls(_) => StatusCode::BAD_GATEWAY,
             ProxyError::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
             ProxyError::UpstreamRequest(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::UpstreamResponseTooLarge(_) => StatusCode::BAD_GATEWAY,
             ProxyError::InvalidToken(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ProxyError::SecretUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::MockResponse(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ProxyError::ServerStart(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -109,13 +164,100 @@ impl ProxyError {
         match self {
             ProxyError::UnknownService(_) => "Unknown service",
             ProxyError::BadRequest(_) => "Invalid request",
+            ProxyError::Forbidden(_) => "Path not allowed for this service",
+            ProxyError::PayloadTooLarge(_) => "Request body too large",
+            ProxyError::TooManyRequests { .. } => "Too many requests",
             ProxyError::UpstreamUnavailable(_) => "Upstream unavailable",
+            ProxyError::UpstreamDns(..) => "Upstream host not found",
+            ProxyError::UpstreamTls(_) => "Upstream TLS error",
             ProxyError::UpstreamTimeout(_) => "Upstream timeout",
             ProxyError::UpstreamRequest(_) => "Upstream error",
+            ProxyError::UpstreamResponseTooLarge(_) => "Upstream response too large",
             ProxyError::InvalidToken(_) => "Configuration error",
+            ProxyError::SecretUnavailable(_) => "Service temporarily misconfigured",
+            ProxyError::MockResponse(_) => "Mock response unavailable",
             ProxyError::ServerStart(_) => "Internal server error",
         }
     }
+
+    /// Seconds the client should wait before retrying, sent as `Retry-After`.
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            ProxyError::TooManyRequests { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Whether a connect error came from resolving the upstream host. The
+/// connector reports these with a "dns error" cause in the source chain.
+fn is_dns_error(err: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if e.to_string() == "dns error" {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Whether a connect error came from the TLS handshake, e.g. an invalid or
+/// expired certificate. TLS failures surface as an `io::Error` of kind
+/// `InvalidData` in the error's source chain, which rustls wraps in a
+/// further `io::Error`.
+fn is_tls_error(err: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            if io.kind() == std::io::ErrorKind::InvalidData {
+                return true;
+            }
+            // An io::Error's source() skips the error it wraps, so step into it
+            if let Some(inner) = io.get_ref() {
+                source = Some(inner);
+                continue;
+            }
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Build an error response, adding `Retry-After` when the error has one.
+fn error_response(
+    status: StatusCode,
+    body: serde_json::Value,
+    retry_after: Option<u64>,
+) -> Response {
+    let mut response = (status, axum::Json(body)).into_response();
+    if let Some(secs) = retry_after {
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+    }
+    response
+}
+
+impl ProxyError {
+    /// Build the error response with the request ID in the body, so a user
+    /// can find the matching log lines.
+    pub fn into_response_with_request_id(self, request_id: &str) -> Response {
+        let status = self.status_code();
+        let client_message = self.client_message();
+
+        // Log full details internally — the Display impl includes context
+        // but client_message() is sanitized
+        tracing::error!(
+            status = %status,
+            error = %self,
+            request_id = %request_id,
+            "Proxy error"
+        );
+
+        let body = json!({ "error": client_message, "request_id": request_id });
+        error_response(status, body, self.retry_after())
+    }
 }
 
 impl IntoResponse for ProxyError {
@@ -131,8 +273,8 @@ impl IntoResponse for ProxyError {
             "Proxy error"
         );
 
-        let body = axum::Json(json!({ "error": client_message }));
-        (status, body).into_response()
+        let body = json!({ "error": client_message });
+        error_response(status, body, self.retry_after())
     }
 }
 
@@ -154,3 +296,80 @@ pub enum SandboxError {
     #[error("Landlock not supported (kernel 5.13+ required)")]
     LandlockNotSupported,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_too_many_requests_sets_retry_after() {
+        let response = ProxyError::TooManyRequests {
+            retry_after: Some(30),
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+
+        let response = ProxyError::TooManyRequests { retry_after: None }
+            .into_response_with_request_id("req-1");
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_payload_too_large_status() {
+        let response = ProxyError::PayloadTooLarge("too big".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_failure_is_classified() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A server that answers the TLS ClientHello with plain HTTP
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+            let _ = socket.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+        });
+
+        let err = reqwest::Client::new()
+            .get(format!("https://{}/", addr))
+            .send()
+            .await
+            .unwrap_err();
+        let err = ProxyError::from_reqwest(err);
+        assert!(matches!(err, ProxyError::UpstreamTls(_)), "{:?}", err);
+
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_unresolvable_host_is_dns_error() {
+        // .invalid never resolves (RFC 2606)
+        let err = reqwest::get("https://clawproxy-test.invalid/v1?key=sk-test").await.unwrap_err();
+        let err = ProxyError::from_reqwest(err);
+        let ProxyError::UpstreamDns(host, _) = &err else {
+            panic!("expected a DNS error, got {:?}", err);
+        };
+        assert_eq!(host, "clawproxy-test.invalid");
+        assert!(!err.to_string().contains("sk-test"));
+
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_refused_connection_is_not_tls() {
+        let err = reqwest::get("https://127.0.0.1:1/").await.unwrap_err();
+        assert!(matches!(
+            ProxyError::from_reqwest(err),
+            ProxyError::UpstreamUnavailable(_)
+        ));
+    }
+}
@@ -9,16 +9,125 @@ pub mod config;
 pub mod error;
 pub mod proxy;
 pub mod sandbox;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+#[cfg(test)]
+mod test_logs;
 
 pub use error::{Error, Result};
 
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use std::str::FromStr;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, EnvFilter, Layer};
+
+/// Env var selecting the log format when none is passed to `init_tracing`
+pub const LOG_FORMAT_ENV: &str = "CLAWPROXY_LOG_FORMAT";
+
+/// Format of log lines
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, for interactive use
+    #[default]
+    Text,
+    /// One JSON object per line, for log aggregators
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}' (expected text or json)", s)),
+        }
+    }
+}
 
 /// Initialize tracing/logging with environment-based filtering.
-/// Uses RUST_LOG environment variable for filter configuration.
-pub fn init_tracing() {
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
-        .init();
+/// Uses RUST_LOG environment variable for filter configuration, or `level`
+/// (e.g. from a `-v`/`-q` flag) when RUST_LOG isn't set. Lines are written in
+/// `format`, else the format named by CLAWPROXY_LOG_FORMAT, else text.
+/// With the `otel` feature, spans are also exported over OTLP when
+/// OTEL_EXPORTER_OTLP_ENDPOINT is set.
+pub fn init_tracing(level: Option<LevelFilter>, format: Option<LogFormat>) {
+    let rust_log = std::env::var(EnvFilter::DEFAULT_ENV).ok();
+    let format = format
+        .or_else(|| std::env::var(LOG_FORMAT_ENV).ok()?.parse().ok())
+        .unwrap_or_default();
+    let registry = tracing_subscriber::registry()
+        .with(fmt_layer(format, std::io::stdout))
+        .with(log_filter(rust_log.as_deref(), level));
+
+    #[cfg(feature = "otel")]
+    let registry = registry.with(telemetry::otlp_layer());
+
+    registry.init();
+}
+
+/// The log filter: `rust_log` if set and valid, else `level`, else info.
+pub fn log_filter(rust_log: Option<&str>, level: Option<LevelFilter>) -> EnvFilter {
+    let rust_log = rust_log.filter(|directives| !directives.is_empty());
+    if let Some(filter) = rust_log.and_then(|directives| EnvFilter::try_new(directives).ok()) {
+        return filter;
+    }
+    EnvFilter::default().add_directive(level.unwrap_or(LevelFilter::INFO).into())
+}
+
+/// The layer that writes log lines to `writer` in `format`.
+pub fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => fmt::layer().json().with_writer(writer).boxed(),
+    }
+}
+
+/// Flush any buffered trace data before the process exits.
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    telemetry::shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_logs::Captured;
+
+    #[test]
+    fn test_json_log_lines() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber =
+            tracing_subscriber::registry().with(fmt_layer(LogFormat::Json, move || writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(service = "openai", "Matched service");
+            tracing::warn!("Upstream returned an error status");
+        });
+
+        let output = captured.output();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["fields"]["message"], "Matched service");
+        assert_eq!(lines[0]["fields"]["service"], "openai");
+        assert_eq!(lines[1]["level"], "WARN");
+    }
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!("TEXT".parse(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
 }
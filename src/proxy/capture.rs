@@ -0,0 +1,188 @@
+//! Opt-in capture of forwarded exchanges to JSON files, for debugging
+//!
+//! Each exchange goes to its own file in `capture_dir`. The injected
+//! credential never reaches the file: the service's auth header is redacted
+//! and the URL is the one without a query credential.
+
+use axum::http::{HeaderMap, Method, StatusCode};
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::ProxyError;
+
+/// Stands in for the injected credential
+const REDACTED: &str = "***";
+
+/// Numbers captures within this process, so exchanges in the same millisecond
+/// with the same request ID get their own files
+static CAPTURE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// An exchange being captured: the forwarded request, waiting for its response.
+pub struct Capture {
+    path: PathBuf,
+    request_id: String,
+    request: Value,
+}
+
+impl Capture {
+    /// Start capturing a request as it is sent upstream.
+    pub fn new(
+        dir: &Path,
+        request_id: &str,
+        method: &Method,
+        logged_url: &str,
+        headers: &HeaderMap,
+        auth_header: &str,
+        body: &[u8],
+    ) -> Self {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        // The request ID may come from the client, so keep it to safe characters
+        let id: String = request_id
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        let seq = CAPTURE_SEQ.fetch_add(1, Ordering::Relaxed);
+        // The pid keeps proxies sharing a capture dir apart
+        let name = format!("{}-{}-{}-{}.json", millis, std::process::id(), seq, id);
+        Self {
+            path: dir.join(name),
+            request_id: request_id.to_string(),
+            request: json!({
+                "method": method.as_str(),
+                "url": logged_url,
+                "headers": headers_json(headers, Some(auth_header)),
+                "body": body_json(body),
+            }),
+        }
+    }
+
+    /// Write the exchange with the upstream's response. `body` is `None` for a
+    /// streamed response, whose body isn't captured.
+    pub fn finish(self, status: StatusCode, headers: &HeaderMap, body: Option<&[u8]>) {
+        let response = json!({
+            "status": status.as_u16(),
+            "headers": headers_json(headers, None),
+            "body": body.map_or(json!({ "streamed": true }), body_json),
+        });
+        self.write("response", response);
+    }
+
+    /// Write the exchange for a request that got no usable response.
+    pub fn fail(self, error: &ProxyError) {
+        self.write("error", json!(error.to_string()));
+    }
+
+    fn write(self, outcome: &str, value: Value) {
+        let mut exchange = Map::new();
+        exchange.insert("request_id".to_string(), json!(self.request_id));
+        exchange.insert("request".to_string(), self.request);
+        exchange.insert(outcome.to_string(), value);
+
+        let result = std::fs::create_dir_all(self.path.parent().unwrap_or(Path::new(".")))
+            .map_err(crate::error::Error::from)
+            .and_then(|()| {
+                let contents = serde_json::to_vec_pretty(&Value::Object(exchange))?;
+                // Bodies can hold sensitive prompts, so only the owner can read them
+                crate::config::write_atomic(&self.path, &contents, Some(0o600))
+            });
+        match result {
+            Ok(()) => tracing::debug!(path = %self.path.display(), "Captured exchange"),
+            Err(e) => tracing::warn!(
+                path = %self.path.display(),
+                error = %e,
+                "Failed to capture exchange"
+            ),
+        }
+    }
+}
+
+/// Headers as `{name: [values]}`, with every value of `auth_header` (matched
+/// case-insensitively) redacted.
+fn headers_json(headers: &HeaderMap, auth_header: Option<&str>) -> Value {
+    let mut map = Map::new();
+    for (name, value) in headers {
+        let redact = auth_header.is_some_and(|auth| name.as_str().eq_ignore_ascii_case(auth));
+        let value = if redact {
+            REDACTED.to_string()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        };
+        let values = map.entry(name.as_str()).or_insert_with(|| json!([]));
+        if let Value::Array(values) = values {
+            values.push(json!(value));
+        }
+    }
+    Value::Object(map)
+}
+
+fn body_json(body: &[u8]) -> Value {
+    json!(String::from_utf8_lossy(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_capture_redacts_auth_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-ant-secret"));
+        headers.append("accept", HeaderValue::from_static("application/json"));
+        headers.append("accept", HeaderValue::from_static("text/event-stream"));
+
+        let capture = Capture::new(
+            &dir.path().join("captures"),
+            "../req/1",
+            &Method::POST,
+            "https://api.anthropic.com/v1/messages",
+            &headers,
+            "X-Api-Key",
+            b"{\"model\":\"claude\"}",
+        );
+        capture.finish(StatusCode::OK, &HeaderMap::new(), None);
+
+        let files: Vec<_> = std::fs::read_dir(dir.path().join("captures"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].file_name().unwrap().to_string_lossy().ends_with("-req1.json"));
+
+        let text = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(!text.contains("sk-ant-secret"));
+        let exchange: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(exchange["request_id"], "../req/1");
+        assert_eq!(exchange["request"]["headers"]["x-api-key"], json!(["***"]));
+        assert_eq!(
+            exchange["request"]["headers"]["accept"],
+            json!(["application/json", "text/event-stream"])
+        );
+        assert_eq!(exchange["request"]["body"], "{\"model\":\"claude\"}");
+        assert_eq!(exchange["response"]["body"], json!({ "streamed": true }));
+    }
+
+    #[test]
+    fn test_capture_files_are_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        for _ in 0..3 {
+            let capture = Capture::new(
+                dir.path(),
+                "req-1",
+                &Method::GET,
+                "https://api.openai.com/v1/models",
+                &HeaderMap::new(),
+                "Authorization",
+                b"",
+            );
+            capture.finish(StatusCode::OK, &HeaderMap::new(), Some(b"{}"));
+        }
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+}
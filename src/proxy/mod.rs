@@ -1,7 +1,12 @@
 //! HTTP proxy server with credential injection
 
+pub mod capture;
+pub mod client;
 pub mod router;
 pub mod server;
+pub mod stats;
 pub mod substitution;
+pub mod transform;
+pub mod websocket;
 
 pub use server::ProxyServer;
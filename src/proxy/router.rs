@@ -1,32 +1,348 @@
-//! Request routing based on URL prefixes
+//! Request routing based on target host and URL prefixes
 
-use crate::config::ServiceConfig;
+use crate::config::{AuthLocation, ServiceConfig};
+use crate::error::{ConfigError, Result};
+use regex::Regex;
+use reqwest::Url;
 use std::collections::HashMap;
 
-/// Match a request path to a service configuration
+/// Compiled `path_regex` patterns, keyed by service name
+#[derive(Debug, Default)]
+pub struct PathPatterns(HashMap<String, Regex>);
+
+impl PathPatterns {
+    /// Compile the `path_regex` of every service that sets one.
+    pub fn from_services(services: &HashMap<String, ServiceConfig>) -> Result<Self> {
+        let mut patterns = HashMap::new();
+        for (name, service) in services {
+            if let Some(pattern) = &service.path_regex {
+                let regex = Regex::new(pattern).map_err(|e| {
+                    ConfigError::Invalid(format!(
+                        "Invalid path_regex for service '{}': {}",
+                        name, e
+                    ))
+                })?;
+                patterns.insert(name.clone(), regex);
+            }
+        }
+        Ok(Self(patterns))
+    }
+
+    /// The compiled pattern for the named service, if it has one
+    pub fn get(&self, name: &str) -> Option<&Regex> {
+        self.0.get(name)
+    }
+}
+
+/// Match a request path to a service configuration. A service with a
+/// `path_regex` is matched by its pattern instead of its prefix.
 pub fn match_service<'a>(
     path: &str,
     services: &'a HashMap<String, ServiceConfig>,
+    patterns: &PathPatterns,
 ) -> Option<(&'a str, &'a ServiceConfig)> {
     for (name, config) in services {
-        if path.starts_with(&config.prefix) {
+        let matched = match patterns.get(name) {
+            Some(regex) => regex.is_match(path),
+            None => path.starts_with(&config.prefix),
+        };
+        if matched {
             return Some((name.as_str(), config));
         }
     }
     None
 }
 
-/// Rewrite a request path by removing the service prefix
+/// Match a request path to a service, falling back to the named default
+/// service when no prefix matches.
+pub fn match_service_or_default<'a>(
+    path: &str,
+    services: &'a HashMap<String, ServiceConfig>,
+    patterns: &PathPatterns,
+    default_service: Option<&str>,
+) -> Option<(&'a str, &'a ServiceConfig)> {
+    match_service(path, services, patterns).or_else(|| {
+        let name = default_service?;
+        services
+            .get_key_value(name)
+            .map(|(name, config)| (name.as_str(), config))
+    })
+}
+
+/// Match a request's target host (from an absolute-form URI or the Host
+/// header) against each service's `match_host`. Any port is ignored.
+pub fn match_service_by_match_host<'a>(
+    host: &str,
+    services: &'a HashMap<String, ServiceConfig>,
+) -> Option<(&'a str, &'a ServiceConfig)> {
+    let host = strip_port(host);
+    services.iter().find_map(|(name, config)| {
+        let match_host = config.match_host.as_deref()?;
+        match_host
+            .eq_ignore_ascii_case(host)
+            .then_some((name.as_str(), config))
+    })
+}
+
+/// Route a request: a `match_host` match on the target host wins, then path
+/// prefix, then the default service.
+pub fn match_request<'a>(
+    host: Option<&str>,
+    path: &str,
+    services: &'a HashMap<String, ServiceConfig>,
+    patterns: &PathPatterns,
+    default_service: Option<&str>,
+) -> Option<(&'a str, &'a ServiceConfig)> {
+    host.and_then(|host| match_service_by_match_host(host, services))
+        .or_else(|| match_service_or_default(path, services, patterns, default_service))
+}
+
+fn strip_port(host: &str) -> &str {
+    if host.starts_with('[') {
+        // IPv6 literal, keep the brackets
+        return host.split_inclusive(']').next().unwrap_or(host);
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
+/// Match a target host and port (e.g. from a CONNECT request) to the service
+/// whose upstream URL points at that host. The port defaults to 443 for https
+/// upstreams and 80 for http.
+pub fn match_service_by_host<'a>(
+    host: &str,
+    port: Option<u16>,
+    services: &'a HashMap<String, ServiceConfig>,
+) -> Option<(&'a str, &'a ServiceConfig)> {
+    services.iter().find_map(|(name, config)| {
+        let upstream = Url::parse(&config.upstream).ok()?;
+        let upstream_host = upstream.host_str()?;
+        let host_matches = upstream_host.eq_ignore_ascii_case(host);
+        let port_matches = port.is_none() || port == upstream.port_or_known_default();
+        (host_matches && port_matches).then_some((name.as_str(), config))
+    })
+}
+
+/// Rewrite a request path by removing the service prefix.
+/// A request to the bare prefix rewrites to `/` rather than an empty path.
 pub fn rewrite_path(path: &str, prefix: &str) -> String {
-    path.strip_prefix(prefix).unwrap_or(path).to_string()
+    let rewritten = path.strip_prefix(prefix).unwrap_or(path);
+    if rewritten.is_empty() {
+        "/".to_string()
+    } else {
+        rewritten.to_string()
+    }
+}
+
+/// Rewrite a path matched by a service's `path_regex`. The matched part is
+/// replaced by `path_rewrite`, with `$1` or `${name}` expanding to captures;
+/// without a `path_rewrite` the path is unchanged.
+pub fn rewrite_regex_path(path: &str, regex: &Regex, rewrite: Option<&str>) -> String {
+    match rewrite {
+        Some(rewrite) => regex.replace(path, rewrite).into_owned(),
+        None => path.to_string(),
+    }
+}
+
+/// Resolve `.` and `..` segments in a path as RFC 3986 does, including
+/// percent-encoded dots. Empty segments and a trailing slash are kept. Returns
+/// None when a `..` would climb above the root.
+pub fn normalize_path(path: &str) -> Option<String> {
+    let is_dot = |segment: &str| segment == "." || segment.eq_ignore_ascii_case("%2e");
+    let is_dot_dot = |segment: &str| {
+        let segment = segment.to_ascii_lowercase();
+        matches!(segment.as_str(), ".." | ".%2e" | "%2e." | "%2e%2e")
+    };
+
+    let (root, relative) = match path.strip_prefix('/') {
+        Some(relative) => ("/", relative),
+        None => ("", path),
+    };
+    let mut output: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in relative.split('/') {
+        trailing_slash = is_dot(segment) || is_dot_dot(segment);
+        if is_dot_dot(segment) {
+            output.pop()?;
+        } else if !is_dot(segment) {
+            output.push(segment);
+        }
+    }
+
+    let mut normalized = format!("{}{}", root, output.join("/"));
+    if trailing_slash && !normalized.ends_with('/') {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/// The path sent upstream for a request path. `pattern` is the service's
+/// compiled `path_regex`, which replaces prefix stripping.
+///
+/// Dot segments in the rewritten path are resolved, so a request can't reach
+/// upstream paths outside the service. Returns None if the path would climb
+/// above the service root, e.g. `/openai/v1/../../admin`.
+pub fn upstream_path(
+    service: &ServiceConfig,
+    pattern: Option<&Regex>,
+    path: &str,
+) -> Option<String> {
+    let rewritten = match pattern {
+        Some(regex) => rewrite_regex_path(path, regex, service.path_rewrite.as_deref()),
+        None => rewrite_path(path, &service.prefix),
+    };
+    normalize_path(&rewritten)
 }
 
-/// Build the upstream URL from service config and request path
-pub fn build_upstream_url(service: &ServiceConfig, path: &str, query: Option<&str>) -> String {
-    let rewritten = rewrite_path(path, &service.prefix);
-    match query {
-        Some(q) => format!("{}{}?{}", service.upstream, rewritten, q),
-        None => format!("{}{}", service.upstream, rewritten),
+/// Whether a service's `allow_paths` and `deny_paths` let a request reach
+/// `upstream_path`. A deny match always refuses; with `allow_paths` set, the
+/// path must match one of them. The path is first put in the form most servers
+/// route on, see [`filter_path`], so `/v1/%66iles` or `/v1//files` can't slip
+/// past a `/v1/files` entry.
+pub fn path_allowed(service: &ServiceConfig, upstream_path: &str) -> bool {
+    let path = filter_path(upstream_path);
+    let matches_any =
+        |patterns: &[String]| patterns.iter().any(|pattern| path_filter_matches(pattern, &path));
+
+    if service.deny_paths.as_deref().is_some_and(matches_any) {
+        return false;
+    }
+    service.allow_paths.as_deref().is_none_or(matches_any)
+}
+
+/// The path `allow_paths` and `deny_paths` are matched against: percent-decoded,
+/// with `;params` dropped from each segment and empty segments removed, since
+/// upstreams commonly route `/v1//files` and `/v1/files;x` to `/v1/files`.
+fn filter_path(upstream_path: &str) -> String {
+    let decoded = percent_decode(upstream_path);
+    let mut path = String::with_capacity(decoded.len());
+    for segment in decoded.split('/') {
+        let segment = segment.split(';').next().unwrap_or_default();
+        if !segment.is_empty() {
+            path.push('/');
+            path.push_str(segment);
+        }
+    }
+    if path.is_empty() || decoded.ends_with('/') {
+        path.push('/');
+    }
+    path
+}
+
+/// Match a path against an `allow_paths`/`deny_paths` entry. An entry with `*`
+/// is a glob, where `*` matches within one segment and `**` across segments.
+/// Otherwise it is a prefix of whole segments: `/v1/files` matches
+/// `/v1/files/abc` but not `/v1/filesystem`.
+fn path_filter_matches(pattern: &str, path: &str) -> bool {
+    if pattern.contains('*') {
+        return glob_matches(pattern.as_bytes(), path.as_bytes());
+    }
+    path.strip_prefix(pattern.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_matches(rest, &path[i..])),
+        [b'*', rest @ ..] => {
+            let segment_end = path.iter().position(|&b| b == b'/').unwrap_or(path.len());
+            (0..=segment_end).any(|i| glob_matches(rest, &path[i..]))
+        }
+        [c, rest @ ..] => path.first() == Some(c) && glob_matches(rest, &path[1..]),
+    }
+}
+
+/// Decode `%XX` escapes in a path. Invalid escapes are kept as-is.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Build the upstream URL from service config and request path: the upstream,
+/// its `upstream_path_prefix`, [`upstream_path`] and the query. None if the
+/// path would climb above the service root.
+pub fn build_upstream_url(
+    service: &ServiceConfig,
+    pattern: Option<&Regex>,
+    path: &str,
+    query: Option<&str>,
+) -> Option<String> {
+    let rewritten = upstream_path(service, pattern, path)?;
+    let prefix = service.upstream_path_prefix.as_deref().unwrap_or("").trim_end_matches('/');
+    Some(match query {
+        Some(q) => format!("{}{}{}?{}", service.upstream, prefix, rewritten, q),
+        None => format!("{}{}{}", service.upstream, prefix, rewritten),
+    })
+}
+
+/// Whether `url` still points at the service's upstream: same scheme, host,
+/// port and credentials. The request path is appended to the upstream as a
+/// string, so a path like `/openai.evil.com` or `/openai@evil.com` could
+/// otherwise change the host.
+pub fn targets_upstream(service: &ServiceConfig, url: &str) -> bool {
+    let (Ok(upstream), Ok(url)) = (Url::parse(&service.upstream), Url::parse(url)) else {
+        return false;
+    };
+    url.scheme() == upstream.scheme()
+        && url.host() == upstream.host()
+        && url.port_or_known_default() == upstream.port_or_known_default()
+        && url.username() == upstream.username()
+        && url.password() == upstream.password()
+}
+
+/// Set query parameter `name` to `value`, replacing any values the client sent
+/// for it. The rest of the query is kept as-is, percent-encoding included.
+pub fn with_query_param(query: Option<&str>, name: &str, value: &str) -> String {
+    let encoded_name: String = url::form_urlencoded::byte_serialize(name.as_bytes()).collect();
+    let encoded_value: String = url::form_urlencoded::byte_serialize(value.as_bytes()).collect();
+
+    let mut pairs: Vec<&str> = query
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or(pair);
+            !pair.is_empty() && key != name && key != encoded_name
+        })
+        .collect();
+    let injected = format!("{}={}", encoded_name, encoded_value);
+    pairs.push(&injected);
+    pairs.join("&")
+}
+
+/// Build the upstream URL, adding the credential as a query parameter when
+/// the service injects it there. None when the path climbs above the service
+/// root, as for [`build_upstream_url`].
+pub fn build_authenticated_url(
+    service: &ServiceConfig,
+    pattern: Option<&Regex>,
+    path: &str,
+    query: Option<&str>,
+    auth_value: &str,
+) -> Option<String> {
+    match (service.auth_in, service.auth_param.as_deref()) {
+        (AuthLocation::Query, Some(param)) => {
+            let query = with_query_param(query, param, auth_value);
+            build_upstream_url(service, pattern, path, Some(&query))
+        }
+        _ => build_upstream_url(service, pattern, path, query),
     }
 }
 
@@ -44,6 +360,18 @@ mod tests {
                 secret: "openai".to_string(),
                 auth_header: "Authorization".to_string(),
                 auth_format: "Bearer {secret}".to_string(),
+                ..Default::default()
+            },
+        );
+        services.insert(
+            "anthropic".to_string(),
+            ServiceConfig {
+                prefix: "/anthropic".to_string(),
+                upstream: "https://api.anthropic.com".to_string(),
+                secret: "anthropic".to_string(),
+                auth_header: "x-api-key".to_string(),
+                auth_format: "{secret}".to_string(),
+                ..Default::default()
             },
         );
         services
@@ -56,12 +384,14 @@ mod tests {
             rewrite_path("/anthropic/v1/messages", "/anthropic"),
             "/v1/messages"
         );
+        assert_eq!(rewrite_path("/openai", "/openai"), "/");
     }
 
     #[test]
     fn test_match_service() {
         let services = test_services();
-        let result = match_service("/openai/v1/chat/completions", &services);
+        let patterns = PathPatterns::default();
+        let result = match_service("/openai/v1/chat/completions", &services, &patterns);
         assert!(result.is_some());
         let (name, _) = result.unwrap();
         assert_eq!(name, "openai");
@@ -70,19 +400,335 @@ mod tests {
     #[test]
     fn test_match_service_no_match() {
         let services = test_services();
-        let result = match_service("/unknown/path", &services);
+        let result = match_service("/unknown/path", &services, &PathPatterns::default());
         assert!(result.is_none());
     }
 
+    #[test]
+    fn test_match_service_or_default_uses_default() {
+        let services = test_services();
+        let patterns = PathPatterns::default();
+        let (name, service) =
+            match_service_or_default("/unknown/path", &services, &patterns, Some("openai"))
+                .unwrap();
+        assert_eq!(name, "openai");
+
+        // Unmatched paths don't start with the default's prefix and pass through unchanged
+        let url = build_upstream_url(service, None, "/unknown/path", None);
+        assert_eq!(url.unwrap(), "https://api.openai.com/unknown/path");
+    }
+
+    #[test]
+    fn test_match_service_or_default_prefers_prefix() {
+        let mut services = test_services();
+        let mut other = services.get("openai").unwrap().clone();
+        other.prefix = "/other".to_string();
+        services.insert("other".to_string(), other);
+
+        let patterns = PathPatterns::default();
+        let (name, _) =
+            match_service_or_default("/openai/v1/chat", &services, &patterns, Some("other"))
+                .unwrap();
+        assert_eq!(name, "openai");
+    }
+
+    #[test]
+    fn test_match_service_or_default_without_default() {
+        let services = test_services();
+        let patterns = PathPatterns::default();
+        assert!(match_service_or_default("/unknown/path", &services, &patterns, None).is_none());
+    }
+
+    #[test]
+    fn test_match_request_by_host() {
+        let mut services = test_services();
+        services.get_mut("anthropic").unwrap().match_host = Some("api.anthropic.com".to_string());
+        let patterns = PathPatterns::default();
+        let route = |host, path| match_request(Some(host), path, &services, &patterns, None);
+
+        let (name, _) = route("api.anthropic.com", "/v1/messages").unwrap();
+        assert_eq!(name, "anthropic");
+
+        // Port and case are ignored
+        let (name, _) = route("API.Anthropic.com:443", "/v1/messages").unwrap();
+        assert_eq!(name, "anthropic");
+
+        // Host match wins over a matching prefix
+        let (name, _) = route("api.anthropic.com", "/openai/v1/chat").unwrap();
+        assert_eq!(name, "anthropic");
+    }
+
+    #[test]
+    fn test_match_request_falls_back_to_prefix() {
+        let mut services = test_services();
+        services.get_mut("anthropic").unwrap().match_host = Some("api.anthropic.com".to_string());
+        let patterns = PathPatterns::default();
+
+        let (name, _) =
+            match_request(Some("localhost:8080"), "/openai/v1/chat", &services, &patterns, None)
+                .unwrap();
+        assert_eq!(name, "openai");
+
+        let (name, _) =
+            match_request(None, "/anthropic/v1/messages", &services, &patterns, None).unwrap();
+        assert_eq!(name, "anthropic");
+
+        let result =
+            match_request(Some("localhost:8080"), "/v1/messages", &services, &patterns, None);
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_match_service_by_path_regex() {
+        let mut services = test_services();
+        let openai = services.get_mut("openai").unwrap();
+        openai.prefix = "/embed".to_string();
+        openai.path_regex = Some(r"^/v1/[^/]+/embeddings$".to_string());
+        let anthropic = services.get_mut("anthropic").unwrap();
+        anthropic.path_regex = Some(r"^/v1/chat/".to_string());
+        let patterns = PathPatterns::from_services(&services).unwrap();
+
+        let (name, _) = match_service("/v1/text/embeddings", &services, &patterns).unwrap();
+        assert_eq!(name, "openai");
+        let (name, _) = match_service("/v1/chat/completions", &services, &patterns).unwrap();
+        assert_eq!(name, "anthropic");
+
+        // The prefix is no longer used for matching
+        assert!(match_service("/embed/v1", &services, &patterns).is_none());
+        assert!(match_service("/anthropic/v1/messages", &services, &patterns).is_none());
+    }
+
+    #[test]
+    fn test_build_upstream_url_with_regex_captures() {
+        let mut services = test_services();
+        let service = services.get_mut("openai").unwrap();
+        service.path_regex = Some(r"^/models/(?P<model>[^/]+)/embed$".to_string());
+        service.path_rewrite = Some("/v1/${model}/embeddings".to_string());
+        let patterns = PathPatterns::from_services(&services).unwrap();
+        let service = &services["openai"];
+        let pattern = patterns.get("openai");
+
+        let url = build_upstream_url(service, pattern, "/models/small/embed", Some("a=b"));
+        assert_eq!(url.unwrap(), "https://api.openai.com/v1/small/embeddings?a=b");
+
+        // Without a rewrite the matched path is forwarded unchanged
+        let mut service = service.clone();
+        service.path_rewrite = None;
+        let url = build_upstream_url(&service, pattern, "/models/small/embed", None);
+        assert_eq!(url.unwrap(), "https://api.openai.com/models/small/embed");
+    }
+
+    #[test]
+    fn test_build_upstream_url_with_path_prefix() {
+        let mut service = test_services()["openai"].clone();
+        service.prefix = "/groq".to_string();
+        service.upstream = "https://api.groq.com".to_string();
+        service.upstream_path_prefix = Some("/openai".to_string());
+
+        let url = build_upstream_url(&service, None, "/groq/v1/chat", Some("a=b"));
+        assert_eq!(url.unwrap(), "https://api.groq.com/openai/v1/chat?a=b");
+        let url = build_upstream_url(&service, None, "/groq", None);
+        assert_eq!(url.unwrap(), "https://api.groq.com/openai/");
+
+        // A trailing slash on the prefix isn't doubled
+        service.upstream_path_prefix = Some("/openai/".to_string());
+        let url = build_upstream_url(&service, None, "/groq/v1/chat", None);
+        assert_eq!(url.unwrap(), "https://api.groq.com/openai/v1/chat");
+
+        // Dot segments can't climb out of the prefix
+        assert_eq!(build_upstream_url(&service, None, "/groq/../admin", None), None);
+    }
+
+    #[test]
+    fn test_path_patterns_reject_invalid_regex() {
+        let mut services = test_services();
+        services.get_mut("openai").unwrap().path_regex = Some("(unclosed".to_string());
+        assert!(PathPatterns::from_services(&services).is_err());
+    }
+
+    #[test]
+    fn test_strip_port() {
+        assert_eq!(strip_port("api.openai.com:443"), "api.openai.com");
+        assert_eq!(strip_port("api.openai.com"), "api.openai.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+    }
+
+    #[test]
+    fn test_match_service_by_host() {
+        let services = test_services();
+
+        let (name, _) = match_service_by_host("api.openai.com", Some(443), &services).unwrap();
+        assert_eq!(name, "openai");
+        assert!(match_service_by_host("API.OPENAI.COM", None, &services).is_some());
+
+        assert!(match_service_by_host("api.openai.com", Some(80), &services).is_none());
+        assert!(match_service_by_host("evil.example.com", Some(443), &services).is_none());
+    }
+
+    #[test]
+    fn test_targets_upstream_rejects_path_tricks() {
+        let services = test_services();
+        let service = &services["openai"];
+        let target = |path: &str| {
+            let url = build_upstream_url(service, None, path, None).unwrap();
+            targets_upstream(service, &url)
+        };
+
+        assert!(target("/openai/v1/chat"));
+        assert!(target("/openai//evil.com"));
+
+        assert!(!target("/openai.evil.com/v1/chat"));
+        assert!(!target("/openai@evil.com/v1/chat"));
+        assert!(!target("/openai:8443/v1/chat"));
+        assert!(!targets_upstream(service, "http://api.openai.com/v1/chat"));
+        assert!(!targets_upstream(service, "not a url"));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path("/v1/chat").unwrap(), "/v1/chat");
+        assert_eq!(normalize_path("/v1/./chat").unwrap(), "/v1/chat");
+        assert_eq!(normalize_path("/v1/files/../models").unwrap(), "/v1/models");
+        assert_eq!(normalize_path("/v1/%2E%2e/models").unwrap(), "/models");
+        assert_eq!(normalize_path("/v1/..").unwrap(), "/");
+        assert_eq!(normalize_path("/v1/models/").unwrap(), "/v1/models/");
+        assert_eq!(normalize_path("//evil.com").unwrap(), "//evil.com");
+        assert_eq!(normalize_path("/").unwrap(), "/");
+
+        assert_eq!(normalize_path("/.."), None);
+        assert_eq!(normalize_path("/v1/../../admin"), None);
+        assert_eq!(normalize_path("/v1/%2e%2e/%2e%2e/admin"), None);
+    }
+
+    #[test]
+    fn test_build_upstream_url_resolves_dot_segments() {
+        let services = test_services();
+        let service = &services["openai"];
+
+        let url = build_upstream_url(service, None, "/openai/v1/files/../models", None);
+        assert_eq!(url.unwrap(), "https://api.openai.com/v1/models");
+
+        assert_eq!(build_upstream_url(service, None, "/openai/v1/../../admin", None), None);
+        assert_eq!(build_upstream_url(service, None, "/openai/../evil.com", None), None);
+    }
+
+    #[test]
+    fn test_path_allowed() {
+        let mut service = test_services()["openai"].clone();
+        assert!(path_allowed(&service, "/v1/files"));
+
+        service.allow_paths = Some(vec![
+            "/v1/chat/completions".to_string(),
+            "/v1/models".to_string(),
+        ]);
+        service.deny_paths = Some(vec!["/v1/models/*/delete".to_string()]);
+        assert!(path_allowed(&service, "/v1/chat/completions"));
+        assert!(path_allowed(&service, "/v1/models/gpt-4o"));
+        assert!(!path_allowed(&service, "/v1/models/ft-123/delete"));
+        assert!(!path_allowed(&service, "/v1/files"));
+        assert!(!path_allowed(&service, "/v1/chat/completionsx"));
+
+        service.allow_paths = None;
+        service.deny_paths = Some(vec!["/v1/files".to_string(), "/v1/fine_tuning/**".to_string()]);
+        assert!(path_allowed(&service, "/v1/chat/completions"));
+        assert!(path_allowed(&service, "/v1/filesystem"));
+        assert!(!path_allowed(&service, "/v1/files/file-1/content"));
+        assert!(!path_allowed(&service, "/v1/%66iles"));
+        assert!(!path_allowed(&service, "/v1/fine_tuning/jobs/ftjob-1/cancel"));
+        for path in ["/v1//files", "//v1/files", "/v1/files//", "/v1/files;x", "/v1;x/files"] {
+            assert!(!path_allowed(&service, path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_filter_path() {
+        assert_eq!(filter_path("/v1/files"), "/v1/files");
+        assert_eq!(filter_path("//v1///files/"), "/v1/files/");
+        assert_eq!(filter_path("/v1/files;jsessionid=1/abc"), "/v1/files/abc");
+        assert_eq!(filter_path("/v1/%66iles"), "/v1/files");
+        assert_eq!(filter_path("/"), "/");
+        assert_eq!(filter_path("//"), "/");
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        let glob = |pattern: &str, path: &str| glob_matches(pattern.as_bytes(), path.as_bytes());
+        assert!(glob("/v1/*/content", "/v1/file-1/content"));
+        assert!(!glob("/v1/*/content", "/v1/a/b/content"));
+        assert!(glob("/v1/**/content", "/v1/a/b/content"));
+        assert!(glob("/v1/models/*", "/v1/models/"));
+        assert!(!glob("/v1/models/*", "/v1/models/a/b"));
+    }
+
     #[test]
     fn test_build_upstream_url() {
         let services = test_services();
         let service = services.get("openai").unwrap();
 
-        let url = build_upstream_url(service, "/openai/v1/chat", None);
-        assert_eq!(url, "https://api.openai.com/v1/chat");
+        let url = build_upstream_url(service, None, "/openai/v1/chat", None);
+        assert_eq!(url.unwrap(), "https://api.openai.com/v1/chat");
+
+        let url = build_upstream_url(service, None, "/openai/v1/chat", Some("stream=true"));
+        assert_eq!(url.unwrap(), "https://api.openai.com/v1/chat?stream=true");
+    }
+
+    #[test]
+    fn test_build_upstream_url_bare_prefix() {
+        let services = test_services();
+        let service = services.get("openai").unwrap();
+
+        let url = build_upstream_url(service, None, "/openai", None);
+        assert_eq!(url.unwrap(), "https://api.openai.com/");
+
+        let url = build_upstream_url(service, None, "/openai", Some("a=b"));
+        assert_eq!(url.unwrap(), "https://api.openai.com/?a=b");
+    }
+
+    #[test]
+    fn test_build_upstream_url_keeps_percent_encoding() {
+        let services = test_services();
+        let service = services.get("openai").unwrap();
+
+        let path = "/openai/v1/files/file%2Fname";
+        let url = build_upstream_url(service, None, path, Some("q=a%26b")).unwrap();
+        assert_eq!(url, "https://api.openai.com/v1/files/file%2Fname?q=a%26b");
+
+        // The URL reqwest parses from it doesn't decode anything either
+        let parsed = Url::parse(&url).unwrap();
+        assert_eq!(parsed.path(), "/v1/files/file%2Fname");
+        assert_eq!(parsed.query(), Some("q=a%26b"));
+    }
+
+    #[test]
+    fn test_with_query_param() {
+        assert_eq!(with_query_param(None, "key", "abc"), "key=abc");
+        assert_eq!(
+            with_query_param(Some("q=a%26b&alt=json"), "key", "abc"),
+            "q=a%26b&alt=json&key=abc"
+        );
+
+        // Client-supplied values are replaced, and the value is encoded
+        assert_eq!(
+            with_query_param(Some("key=PROXY:google&q=x"), "key", "a&b=c"),
+            "q=x&key=a%26b%3Dc"
+        );
+    }
+
+    #[test]
+    fn test_build_authenticated_url() {
+        let services = test_services();
+        let mut service = services.get("openai").unwrap().clone();
+
+        // Header injection leaves the URL alone
+        let url = build_authenticated_url(&service, None, "/openai/v1/models", Some("a=b"), "sk");
+        assert_eq!(url.unwrap(), "https://api.openai.com/v1/models?a=b");
+
+        service.auth_in = AuthLocation::Query;
+        service.auth_param = Some("key".to_string());
+        let url = build_authenticated_url(&service, None, "/openai/v1/models", Some("a=b"), "sk");
+        assert_eq!(url.unwrap(), "https://api.openai.com/v1/models?a=b&key=sk");
 
-        let url = build_upstream_url(service, "/openai/v1/chat", Some("stream=true"));
-        assert_eq!(url, "https://api.openai.com/v1/chat?stream=true");
+        let url = build_authenticated_url(&service, None, "/openai/v1/models", None, "sk");
+        assert_eq!(url.unwrap(), "https://api.openai.com/v1/models?key=sk");
     }
 }
@@ -3,179 +3,1045 @@
 use axum::{
     body::Body,
     extract::State,
-    http::{Request, Response, StatusCode},
+    http::{
+        header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri,
+    },
     response::IntoResponse,
+    routing::get,
     Router,
 };
 use futures::StreamExt;
-use std::collections::HashMap;
-use std::sync::Arc;
+use serde_json::json;
+use std::future::{Future, IntoFuture};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use tokio::signal;
 use tower_http::trace::TraceLayer;
+use tracing::Instrument;
+use uuid::Uuid;
 
-use crate::config::Config;
+use crate::config::{
+    load_secret_store, AuthLocation, Config, MockResponse, SecretStore, ServiceConfig, Transform,
+};
 use crate::error::{ProxyError, Result};
-use crate::proxy::{router, substitution};
+use crate::proxy::capture::Capture;
+use crate::proxy::client::UpstreamClients;
+use crate::proxy::router::PathPatterns;
+use crate::proxy::stats::{ByteCounters, Stats};
+use crate::proxy::{router, substitution, transform, websocket};
+
+/// Correlation ID header returned to the client and forwarded upstream
+pub const REQUEST_ID_HEADER: &str = "x-clawproxy-request-id";
+
+/// Path of the stats endpoint, namespaced so it can't shadow a service's paths
+pub const STATS_PATH: &str = "/_clawproxy/stats";
+
+/// Longest client-supplied request ID that is reused rather than replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Largest request body the proxy will buffer and forward
+const MAX_REQUEST_BODY_SIZE: usize = 10 * 1024 * 1024;
 
 /// Shared application state passed to handlers via Axum's State extractor.
 #[derive(Clone)]
 struct AppState {
-    config: Arc<Config>,
-    secrets: Arc<HashMap<String, String>>,
-    client: reqwest::Client,
+    /// Current configuration, swapped on reload
+    config: Arc<RwLock<Arc<Config>>>,
+    secrets: SecretStore,
+    /// Upstream clients, rebuilt on reload
+    clients: Arc<RwLock<Arc<UpstreamClients>>>,
+    /// Compiled `path_regex` patterns, rebuilt on reload
+    patterns: Arc<RwLock<Arc<PathPatterns>>>,
+    /// Per-service counters served at `STATS_PATH`
+    stats: Arc<Stats>,
+    /// Requests currently in flight, including responses still streaming
+    active_requests: Arc<AtomicUsize>,
+}
+
+impl AppState {
+    fn new(config: Config, secrets: SecretStore) -> Result<Self> {
+        let clients = UpstreamClients::from_config(&config)?;
+        let patterns = PathPatterns::from_services(&config.services)?;
+        Ok(Self {
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            secrets,
+            clients: Arc::new(RwLock::new(Arc::new(clients))),
+            patterns: Arc::new(RwLock::new(Arc::new(patterns))),
+            stats: Arc::new(Stats::new()),
+            active_requests: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Snapshot of the current configuration
+    fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Snapshot of the current upstream clients
+    fn clients(&self) -> Arc<UpstreamClients> {
+        self.clients.read().unwrap().clone()
+    }
+
+    /// Snapshot of the current path patterns
+    fn patterns(&self) -> Arc<PathPatterns> {
+        self.patterns.read().unwrap().clone()
+    }
+}
+
+/// Counts a request as active until dropped.
+struct ActiveRequestGuard(Arc<AtomicUsize>);
+
+impl ActiveRequestGuard {
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 /// The proxy server that handles incoming requests.
 pub struct ProxyServer {
     config: Config,
-    secrets: HashMap<String, String>,
+    secrets: SecretStore,
+    config_path: Option<PathBuf>,
 }
 
 impl ProxyServer {
     /// Create a new proxy server with the given configuration and pre-loaded secrets.
-    pub fn new(config: Config, secrets: HashMap<String, String>) -> Self {
-        Self { config, secrets }
+    pub fn new(config: Config, secrets: impl Into<SecretStore>) -> Self {
+        Self {
+            config,
+            secrets: secrets.into(),
+            config_path: None,
+        }
+    }
+
+    /// Config file to re-read on SIGHUP. Defaults to the standard config path.
+    pub fn with_config_path(mut self, path: Option<PathBuf>) -> Self {
+        self.config_path = path;
+        self
     }
 
-    /// Start the proxy server, binding to the configured address.
+    /// Addresses the server binds to, from `listen` in the config.
+    pub fn listen_addrs(&self) -> Vec<String> {
+        self.config.listen.bind_addresses()
+    }
+
+    /// Start the proxy server, binding every configured address, or
+    /// `listen.socket_path` when it is set.
     /// Blocks until a shutdown signal (SIGINT/SIGTERM) is received.
+    /// SIGHUP reloads the config and secrets without dropping connections.
     pub async fn run(self) -> Result<()> {
-        let state = AppState {
-            config: Arc::new(self.config.clone()),
-            secrets: Arc::new(self.secrets),
-            client: reqwest::Client::new(),
-        };
+        #[cfg(unix)]
+        if let Some(path) = self.config.listen.socket_path.clone() {
+            // A socket left by a previous run would make bind fail
+            use std::os::unix::fs::FileTypeExt;
+            if std::fs::metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+                std::fs::remove_file(&path)?;
+            }
+            let listener = tokio::net::UnixListener::bind(&path)?;
+            return self.serve(vec![Listener::Unix(listener)]).await;
+        }
+
+        let mut listeners = Vec::new();
+        for addr in self.listen_addrs() {
+            listeners.push(tokio::net::TcpListener::bind(&addr).await?);
+        }
+        self.run_with_listeners(listeners).await
+    }
+
+    /// Like [`run`](Self::run), but serves on an already-bound listener
+    /// instead of `listen` from the config. Binding `127.0.0.1:0` gives an
+    /// ephemeral port that the caller can read before starting the server.
+    pub async fn run_with_listener(self, listener: tokio::net::TcpListener) -> Result<()> {
+        self.run_with_listeners(vec![listener]).await
+    }
+
+    /// Like [`run_with_listener`](Self::run_with_listener), serving the same
+    /// router on each of several listeners.
+    pub async fn run_with_listeners(self, listeners: Vec<tokio::net::TcpListener>) -> Result<()> {
+        self.serve(listeners.into_iter().map(Listener::Tcp).collect()).await
+    }
+
+    async fn serve(self, listeners: Vec<Listener>) -> Result<()> {
+        // Keep expiring secrets fresh for the lifetime of the server
+        let refresh_tasks = Arc::new(Mutex::new(self.secrets.spawn_refresh()));
 
-        let app = Router::new()
-            .fallback(proxy_handler)
-            .layer(TraceLayer::new_for_http())
-            .with_state(state);
+        let drain_timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+        log_startup_summary(&self.config, &self.secrets);
+        let state = AppState::new(self.config, self.secrets)?;
 
-        let addr = format!("{}:{}", self.config.listen.host, self.config.listen.port);
-        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        for listener in &listeners {
+            match listener {
+                Listener::Tcp(tcp) => {
+                    tracing::info!(addr = %tcp.local_addr()?, "Proxy server listening");
+                }
+                #[cfg(unix)]
+                Listener::Unix(unix) => {
+                    tracing::info!(socket = ?unix.local_addr()?, "Proxy server listening");
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        let reload_task = tokio::spawn(reload_on_sighup(
+            state.clone(),
+            self.config_path,
+            refresh_tasks.clone(),
+        ));
 
-        tracing::info!(addr = %addr, "Proxy server listening");
+        // One shutdown signal stops every listener
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let signal_task = tokio::spawn(async move {
+            shutdown_signal().await;
+            let _ = shutdown_tx.send(true);
+        });
+        let servers = listeners.into_iter().map(|listener| {
+            let mut shutdown_rx = shutdown_rx.clone();
+            let signal = async move {
+                let _ = shutdown_rx.wait_for(|signalled| *signalled).await;
+            };
+            serve_with_drain_timeout(listener, state.clone(), signal, drain_timeout)
+        });
+        let result = futures::future::try_join_all(servers).await;
+        signal_task.abort();
+        result?;
 
-        axum::serve(listener, app)
-            .with_graceful_shutdown(shutdown_signal())
-            .await?;
+        #[cfg(unix)]
+        reload_task.abort();
+        for task in refresh_tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
 
         Ok(())
     }
 }
 
+/// Log the effective configuration in one line at startup. Secrets are listed
+/// by name only.
+fn log_startup_summary(config: &Config, secrets: &SecretStore) {
+    let listen = match &config.listen.socket_path {
+        Some(path) => format!("unix:{}", path.display()),
+        None => config.listen.bind_addresses().join(", "),
+    };
+    let mut services: Vec<_> = config.services.iter().collect();
+    services.sort_by_key(|(name, _)| *name);
+    let mappings: Vec<String> = services
+        .iter()
+        .map(|(name, service)| format!("{} {} -> {}", name, service.prefix, service.upstream))
+        .collect();
+
+    tracing::info!(
+        listen = %listen,
+        services = config.services.len(),
+        mappings = %mappings.join(", "),
+        secrets_dir = %config.secrets_dir().display(),
+        secrets_loaded = %secrets.names().join(", "),
+        "Effective configuration"
+    );
+}
+
+/// Re-read the config file and secrets, then swap them into the running server.
+/// The listen address is fixed at startup and is not affected by a reload.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn reload(state: &AppState, config_path: Option<&Path>) -> Result<()> {
+    let config = Config::load(config_path)?;
+    let secrets = load_secret_store(&config.secrets_dir(), &config)?;
+    let clients = UpstreamClients::from_config(&config)?;
+    let patterns = PathPatterns::from_services(&config.services)?;
+
+    let current = state.config();
+    if config.listen.bind_addresses() != current.listen.bind_addresses() {
+        tracing::warn!("Listen address changes require a restart; keeping the current address");
+    }
+
+    state.secrets.replace_with(&secrets);
+    *state.clients.write().unwrap() = Arc::new(clients);
+    *state.patterns.write().unwrap() = Arc::new(patterns);
+    *state.config.write().unwrap() = Arc::new(config);
+    Ok(())
+}
+
+/// Reload the config and secrets each time SIGHUP is received.
+#[cfg(unix)]
+async fn reload_on_sighup(
+    state: AppState,
+    config_path: Option<PathBuf>,
+    refresh_tasks: Arc<Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+) {
+    let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to install SIGHUP handler; reload disabled");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        tracing::info!("Reload signal received");
+        match reload(&state, config_path.as_deref()) {
+            Ok(()) => {
+                let mut tasks = refresh_tasks.lock().unwrap();
+                for task in tasks.drain(..) {
+                    task.abort();
+                }
+                *tasks = state.secrets.spawn_refresh();
+                tracing::info!(
+                    services = state.config().services.len(),
+                    "Configuration reloaded"
+                );
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Reload failed; keeping the previous configuration");
+            }
+        }
+    }
+}
+
+/// A bound socket the proxy serves on.
+enum Listener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+/// Serve until `signal` resolves, then wait up to `drain_timeout` for in-flight
+/// requests to finish before returning anyway.
+async fn serve_with_drain_timeout(
+    listener: Listener,
+    state: AppState,
+    signal: impl Future<Output = ()> + Send + 'static,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let listener = match listener {
+        Listener::Tcp(listener) => listener,
+        #[cfg(unix)]
+        Listener::Unix(listener) => {
+            return serve_unix_with_drain_timeout(listener, state, signal, drain_timeout).await;
+        }
+    };
+    let active_requests = state.active_requests.clone();
+    let (signalled_tx, mut signalled_rx) = tokio::sync::watch::channel(false);
+
+    let server = axum::serve(listener, build_router(state)).with_graceful_shutdown(async move {
+        signal.await;
+        let _ = signalled_tx.send(true);
+    });
+
+    let drain_deadline = async move {
+        let _ = signalled_rx.wait_for(|signalled| *signalled).await;
+        tokio::time::sleep(drain_timeout).await;
+    };
+
+    tokio::select! {
+        result = server.into_future() => result?,
+        _ = drain_deadline => {
+            tracing::warn!(
+                active_requests = active_requests.load(Ordering::SeqCst),
+                timeout_secs = drain_timeout.as_secs(),
+                "Shutdown drain timeout elapsed, closing remaining connections"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Serve HTTP over a Unix domain socket until `signal` resolves, then wait up to
+/// `drain_timeout` for in-flight requests. `axum::serve` only accepts TCP
+/// listeners, so connections are handed to hyper directly.
+#[cfg(unix)]
+async fn serve_unix_with_drain_timeout(
+    listener: tokio::net::UnixListener,
+    state: AppState,
+    signal: impl Future<Output = ()> + Send + 'static,
+    drain_timeout: Duration,
+) -> Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto;
+    use hyper_util::service::TowerToHyperService;
+
+    let active_requests = state.active_requests.clone();
+    let router = build_router(state);
+
+    tokio::pin!(signal);
+    loop {
+        let (socket, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut signal => break,
+        };
+        let service = TowerToHyperService::new(router.clone());
+        tokio::spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(socket), service);
+            if let Err(e) = connection.await {
+                tracing::debug!(error = %e, "Unix socket connection ended with an error");
+            }
+        });
+    }
+
+    let drained = async {
+        while active_requests.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    if tokio::time::timeout(drain_timeout, drained).await.is_err() {
+        tracing::warn!(
+            active_requests = active_requests.load(Ordering::SeqCst),
+            timeout_secs = drain_timeout.as_secs(),
+            "Shutdown drain timeout elapsed, closing remaining connections"
+        );
+    }
+
+    Ok(())
+}
+
+/// Build the application router with the proxy handler as a catch-all.
+fn build_router(state: AppState) -> Router {
+    Router::new()
+        .route(STATS_PATH, get(stats_handler))
+        .fallback(proxy_handler)
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+/// Report per-service request counters. No service or secret is involved.
+async fn stats_handler(State(state): State<AppState>) -> axum::Json<serde_json::Value> {
+    axum::Json(state.stats.snapshot())
+}
+
+/// Map a failure reading the client's body, telling an oversized body apart
+/// from a broken one.
+fn body_read_error(err: axum::Error) -> ProxyError {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(&err);
+    while let Some(e) = source {
+        if e.is::<http_body_util::LengthLimitError>() {
+            return ProxyError::PayloadTooLarge(format!(
+                "request body exceeds {} bytes",
+                MAX_REQUEST_BODY_SIZE
+            ));
+        }
+        source = e.source();
+    }
+    ProxyError::BadRequest(err.to_string())
+}
+
 /// Catch-all handler that routes, rewrites, injects credentials, and forwards requests.
 async fn proxy_handler(
     State(state): State<AppState>,
     request: Request<Body>,
 ) -> Response<Body> {
-    match forward_request(&state, request).await {
-        Ok(response) => response,
-        Err(e) => e.into_response(),
+    let guard = ActiveRequestGuard::new(&state.active_requests);
+    let request_id = request_id_for(request.headers());
+
+    if request.method() == Method::CONNECT {
+        return connect_response(&state.config(), request.uri(), &request_id);
+    }
+
+    let mut response = match forward_request(&state, request, &request_id).await {
+        Ok(response) => {
+            // Keep the request counted as active until its body finishes streaming
+            let (parts, body) = response.into_parts();
+            let stream = body.into_data_stream().map(move |chunk| {
+                let _held = &guard;
+                chunk
+            });
+            Response::from_parts(parts, Body::from_stream(stream))
+        }
+        Err(e) => e.into_response_with_request_id(&request_id),
+    };
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// The host a request is aimed at: the authority of an absolute-form URI, as
+/// sent by clients using the proxy via `HTTP_PROXY`, or else the Host header.
+fn request_target_host<B>(request: &Request<B>) -> Option<String> {
+    if let Some(authority) = request.uri().authority() {
+        return Some(authority.as_str().to_string());
     }
+    request
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .map(|h| h.to_string())
+}
+
+/// Respond to a CONNECT request.
+///
+/// A CONNECT tunnel carries end-to-end TLS between the client and the
+/// upstream, so the proxy never sees the request headers and cannot inject
+/// credentials into them. Instead of tunnelling traffic that would then fail
+/// authentication, the target host is mapped to its service and the client is
+/// told which plain-HTTP base URL to use. Hosts that aren't configured are
+/// refused outright.
+fn connect_response(config: &Config, target: &Uri, request_id: &str) -> Response<Body> {
+    let Some(authority) = target.authority() else {
+        return ProxyError::BadRequest(format!("Invalid CONNECT target: {}", target))
+            .into_response_with_request_id(request_id);
+    };
+
+    let matched =
+        router::match_service_by_host(authority.host(), authority.port_u16(), &config.services);
+    let (status, body) = match matched {
+        Some((service_name, service)) => {
+            tracing::warn!(
+                target = %authority,
+                service = service_name,
+                "Refusing CONNECT tunnel; credentials can't be injected into TLS traffic"
+            );
+            let mut body = json!({
+                "error": "CONNECT tunnelling is not supported, use the service base URL instead",
+                "service": service_name,
+                "request_id": request_id,
+            });
+            // A proxy on a Unix socket has no base URL; name the socket instead
+            match &config.listen.socket_path {
+                Some(path) => body["socket_path"] = json!(path.display().to_string()),
+                None => {
+                    let address = &config.listen.bind_addresses()[0];
+                    body["base_url"] = json!(format!("http://{}{}", address, service.prefix));
+                }
+            }
+            (StatusCode::NOT_IMPLEMENTED, body)
+        }
+        None => {
+            tracing::warn!(target = %authority, "Refusing CONNECT to unconfigured host");
+            (
+                StatusCode::FORBIDDEN,
+                json!({
+                    "error": "Host not allowed",
+                    "request_id": request_id,
+                }),
+            )
+        }
+    };
+
+    (status, axum::Json(body)).into_response()
+}
+
+/// Reuse the client's request ID if it sent a usable one, otherwise generate one.
+fn request_id_for(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
 }
 
 /// Forward a request to the matched upstream service with credential injection.
+#[tracing::instrument(
+    name = "proxy_request",
+    skip_all,
+    fields(
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        service = tracing::field::Empty,
+        upstream_host = tracing::field::Empty,
+    )
+)]
 async fn forward_request(
     state: &AppState,
     request: Request<Body>,
+    request_id: &str,
 ) -> std::result::Result<Response<Body>, ProxyError> {
     let method = request.method().clone();
     let path = request.uri().path().to_string();
-    let query = request.uri().query().map(|q| q.to_string());
 
     tracing::info!(%method, %path, "Request received");
 
-    // Match the request path to a configured service
-    let (service_name, service) = router::match_service(&path, &state.config.services)
-        .ok_or_else(|| ProxyError::UnknownService(path.clone()))?;
+    let config = state.config();
+    let patterns = state.patterns();
+    let (service_name, service) = route_request(&config, &patterns, &request)?;
 
-    tracing::info!(service = service_name, %path, "Matched service");
+    let upstream_path = router::upstream_path(service, patterns.get(service_name), &path);
+    if upstream_path.is_some_and(|upstream_path| !router::path_allowed(service, &upstream_path)) {
+        tracing::warn!(service = service_name, %path, "Path not allowed for service");
+        return Err(ProxyError::Forbidden(format!(
+            "Path not allowed for service '{}': {}",
+            service_name, path
+        )));
+    }
 
-    // Build the upstream URL with rewritten path
-    let upstream_url = router::build_upstream_url(service, &path, query.as_deref());
+    let started = Instant::now();
+    let result = if let Some(mock) = &service.mock_response {
+        // A mocked service answers without contacting the upstream or reading secrets
+        tracing::info!(service = service_name, "Returning mock response");
+        mock_response(mock, &config).await
+    } else if service.websocket && websocket::is_upgrade_request(request.headers()) {
+        upgrade_websocket(state, &config, &patterns, request, request_id).await
+    } else {
+        send_upstream(state, &config, &patterns, service, request, request_id).await
+    };
 
-    // Look up the secret for this service
-    let secret = state
-        .secrets
-        .get(&service.secret)
-        .ok_or_else(|| ProxyError::InvalidToken(service.secret.clone()))?;
+    let status = result.as_ref().ok().map(|response| response.status().as_u16());
+    state.stats.record(service_name, started.elapsed(), status);
+    result
+}
 
-    // Format the auth header value
-    let auth_value = substitution::format_auth_header(&service.auth_format, secret);
+/// Inject credentials into a request for `service` and send it upstream.
+async fn send_upstream(
+    state: &AppState,
+    config: &Config,
+    patterns: &PathPatterns,
+    service: &ServiceConfig,
+    request: Request<Body>,
+    request_id: &str,
+) -> std::result::Result<Response<Body>, ProxyError> {
+    let upstream =
+        prepare_upstream_request(config, patterns, &state.secrets, &request, request_id)?;
+
+    let upstream_host = reqwest::Url::parse(&service.upstream)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_string()))
+        .unwrap_or_default();
+    let span = tracing::Span::current();
+    span.record("service", upstream.service.as_str());
+    span.record("upstream_host", upstream_host.as_str());
 
     // Build the upstream request
-    let method = request.method().clone();
-    let mut req_builder = state.client.request(method, &upstream_url);
+    let clients = state.clients();
+    let mut req_builder = clients
+        .for_service(&upstream.service)
+        .request(upstream.method.clone(), &upstream.url);
+    let mut headers = upstream.headers;
 
-    // Copy headers, skipping Host and the service's auth header
-    let auth_header_lower = service.auth_header.to_lowercase();
-    for (name, value) in request.headers() {
-        if name == "host" || name.as_str().to_lowercase() == auth_header_lower {
-            continue;
+    // Read the request body. Any body rewriting happens between here and the
+    // length check below.
+    let original_body = axum::body::to_bytes(request.into_body(), MAX_REQUEST_BODY_SIZE)
+        .await
+        .map_err(body_read_error)?;
+    let body_bytes = match service.transform {
+        Some(transform) if !original_body.is_empty() => {
+            transform::translate_request(transform, &original_body)?.into()
         }
-        req_builder = req_builder.header(name, value);
+        _ => original_body.clone(),
+    };
+
+    // A rewritten body no longer matches the client's Content-Length, so let
+    // reqwest set it. Transfer-Encoding is hop-by-hop and already removed.
+    if body_bytes != original_body {
+        headers.remove(header::CONTENT_LENGTH);
     }
 
-    // Inject the auth header
-    req_builder = req_builder.header(&service.auth_header, &auth_value);
+    let capture = config.capture_dir_for(service).map(|dir| {
+        Capture::new(
+            &dir,
+            request_id,
+            &upstream.method,
+            &upstream.logged_url,
+            &headers,
+            &service.auth_header,
+            &body_bytes,
+        )
+    });
 
-    // Forward the request body
-    let body_bytes = axum::body::to_bytes(request.into_body(), 10 * 1024 * 1024)
-        .await
-        .map_err(|e| ProxyError::BadRequest(e.to_string()))?;
+    req_builder = req_builder.headers(headers);
+    let sent_bytes = body_bytes.len();
     if !body_bytes.is_empty() {
         req_builder = req_builder.body(body_bytes);
     }
 
-    tracing::debug!(upstream = %upstream_url, "Forwarding request");
+    tracing::debug!(upstream = %upstream.logged_url, "Forwarding request");
 
     // Send the request upstream
+    let byte_counters = state.stats.byte_counters(&upstream.service);
     let upstream_response = req_builder
         .send()
+        .instrument(tracing::info_span!(
+            "upstream_request",
+            service = upstream.service.as_str(),
+            upstream_host = %upstream_host,
+        ))
         .await
-        .map_err(ProxyError::from_reqwest)?;
+        .map_err(ProxyError::from_reqwest);
+    let upstream_response = match upstream_response {
+        Ok(response) => response,
+        Err(e) => {
+            if let Some(capture) = capture {
+                capture.fail(&e);
+            }
+            return Err(e);
+        }
+    };
+    byte_counters.add_sent(sent_bytes);
+
+    let status = upstream_response.status();
+    if status.is_client_error() || status.is_server_error() {
+        tracing::warn!(status = status.as_u16(), "Upstream returned an error status");
+    } else {
+        tracing::info!(status = status.as_u16(), "Upstream responded");
+    }
 
     // Convert and return the response
-    convert_response(upstream_response).await
+    let stream_content_types = stream_content_types(config, service);
+    convert_response(
+        upstream_response,
+        stream_content_types,
+        config.upstream.max_response_bytes,
+        service.transform,
+        byte_counters,
+        capture,
+    )
+    .await
+}
+
+/// Inject credentials into a WebSocket handshake and relay the connection.
+/// The upstream handshake doesn't go through the HTTP client, so `upstream`
+/// client settings such as `outbound_proxy` don't apply to it.
+async fn upgrade_websocket(
+    state: &AppState,
+    config: &Config,
+    patterns: &PathPatterns,
+    request: Request<Body>,
+    request_id: &str,
+) -> std::result::Result<Response<Body>, ProxyError> {
+    let upstream =
+        prepare_upstream_request(config, patterns, &state.secrets, &request, request_id)?;
+    tracing::debug!(upstream = %upstream.logged_url, "Opening WebSocket");
+    websocket::proxy(request, &upstream.url, &upstream.logged_url, upstream.headers).await
+}
+
+/// Match the request's target host or path to a configured service.
+fn route_request<'a, B>(
+    config: &'a Config,
+    patterns: &PathPatterns,
+    request: &Request<B>,
+) -> std::result::Result<(&'a str, &'a ServiceConfig), ProxyError> {
+    let path = request.uri().path();
+    let target_host = request_target_host(request);
+    router::match_request(
+        target_host.as_deref(),
+        path,
+        &config.services,
+        patterns,
+        config.default_service.as_deref(),
+    )
+    .ok_or_else(|| ProxyError::UnknownService(path.to_string()))
+}
+
+/// Build a service's canned response. A `body_file` is read on each request,
+/// so it can be edited while the proxy runs.
+async fn mock_response(
+    mock: &MockResponse,
+    config: &Config,
+) -> std::result::Result<Response<Body>, ProxyError> {
+    let body = match mock.body_file_path(config) {
+        Some(path) => tokio::fs::read(&path).await.map_err(|e| {
+            ProxyError::MockResponse(format!(
+                "Failed to read mock_response body_file {}: {}",
+                path.display(),
+                e
+            ))
+        })?,
+        None => mock.body.clone().unwrap_or_default().into_bytes(),
+    };
+
+    Response::builder()
+        .status(mock.status)
+        .header(header::CONTENT_TYPE, &mock.content_type)
+        .body(Body::from(body))
+        .map_err(|e| ProxyError::MockResponse(format!("Invalid mock_response: {}", e)))
+}
+
+/// An upstream request computed from a client request, ready to send apart
+/// from the body.
+#[derive(Debug)]
+struct UpstreamRequest {
+    /// Name of the matched service
+    service: String,
+    method: Method,
+    /// URL to send to, including a query-parameter credential if any
+    url: String,
+    /// The same URL without the credential, for logging
+    logged_url: String,
+    headers: HeaderMap,
+}
+
+/// Route a client request to its service and compute the upstream method, URL
+/// and headers with the service's credential injected. Nothing is sent.
+fn prepare_upstream_request<B>(
+    config: &Config,
+    patterns: &PathPatterns,
+    secrets: &SecretStore,
+    request: &Request<B>,
+    request_id: &str,
+) -> std::result::Result<UpstreamRequest, ProxyError> {
+    let path = request.uri().path();
+    let query = request.uri().query();
+
+    let (service_name, service) = route_request(config, patterns, request)?;
+    let pattern = patterns.get(service_name);
+
+    tracing::info!(service = service_name, %path, "Matched service");
+
+    // Look up the secret for this service. Secrets are checked at startup, so
+    // a miss here means it expired or went away in a reload.
+    let secret = secrets.get(&service.secret).ok_or_else(|| {
+        let reason = if secrets.contains(&service.secret) {
+            "expired and could not be refreshed"
+        } else {
+            "not loaded; it may have been deleted or a reload is in progress"
+        };
+        tracing::error!(
+            service = service_name,
+            secret = service.secret.as_str(),
+            "Secret for matched service is {}",
+            reason
+        );
+        ProxyError::SecretUnavailable(format!("{}: {}", service.secret, reason))
+    })?;
+
+    // Format the auth header value
+    let auth_value = substitution::format_auth_header(&service.auth_format, &secret);
+
+    // Build the upstream URL with rewritten path, plus the credential when it
+    // goes in the query
+    let (Some(mut url), Some(mut logged_url)) = (
+        router::build_authenticated_url(service, pattern, path, query, &auth_value),
+        router::build_upstream_url(service, pattern, path, query),
+    ) else {
+        tracing::warn!(service = service_name, %path, "Request path climbs above the service root");
+        return Err(ProxyError::BadRequest(format!(
+            "Request path climbs above the root of service '{}': {}",
+            service_name, path
+        )));
+    };
+    if let Some(transform) = service.transform {
+        url = transform::upstream_url(transform, &url);
+        logged_url = transform::upstream_url(transform, &logged_url);
+    }
+    if !router::targets_upstream(service, &url) {
+        tracing::warn!(service = service_name, %path, "Request path escapes the upstream host");
+        return Err(ProxyError::BadRequest(format!(
+            "Request path escapes the upstream host of service '{}': {}",
+            service_name, path
+        )));
+    }
+
+    // Copy headers with the service's auth header replaced by the injected one
+    let mut headers = build_upstream_headers(request.headers(), service, &auth_value)?;
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        headers.insert(REQUEST_ID_HEADER, value);
+    }
+    if let Some(transform) = service.transform {
+        transform::add_headers(transform, &mut headers);
+    }
+
+    Ok(UpstreamRequest {
+        service: service_name.to_string(),
+        method: request.method().clone(),
+        url,
+        logged_url,
+        headers,
+    })
+}
+
+/// Build the headers sent upstream from the client's headers.
+///
+/// Every value of a multi-valued header is kept. Host, hop-by-hop headers and
+/// headers excluded by `forward_headers` are dropped, and all client-supplied
+/// values of the service's auth header (matched case-insensitively) are
+/// replaced by exactly one injected value.
+///
+/// A client value that isn't a `PROXY:` placeholder is a credential of the
+/// client's own: replacing it is logged at debug level, or refused with 400
+/// when the service sets `reject_client_auth`.
+///
+/// Services with `auth_in: query` get no auth header.
+fn build_upstream_headers(
+    incoming: &HeaderMap,
+    service: &ServiceConfig,
+    auth_value: &str,
+) -> std::result::Result<HeaderMap, ProxyError> {
+    if service.auth_in == AuthLocation::Query {
+        return Ok(client_headers(incoming, service));
+    }
+
+    // HeaderName parsing normalizes to lowercase, so this matches any casing
+    let auth_name = HeaderName::from_bytes(service.auth_header.as_bytes()).map_err(|_| {
+        ProxyError::InvalidToken(format!("Invalid auth header name: {}", service.auth_header))
+    })?;
+    let auth_value = HeaderValue::from_str(auth_value)
+        .map_err(|_| ProxyError::InvalidToken("Invalid auth header value".to_string()))?;
+
+    let client_credential = incoming
+        .get_all(&auth_name)
+        .iter()
+        .any(|value| !value.to_str().is_ok_and(substitution::contains_proxy_token));
+    if client_credential {
+        if service.reject_client_auth {
+            return Err(ProxyError::BadRequest(format!(
+                "Client supplied its own {} header",
+                service.auth_header
+            )));
+        }
+        tracing::debug!(
+            header = %service.auth_header,
+            "Replacing client-supplied auth header with the injected credential"
+        );
+    }
+
+    let mut headers = client_headers(incoming, service);
+    headers.remove(&auth_name);
+    headers.insert(auth_name, auth_value);
+
+    Ok(headers)
+}
+
+/// The client's headers that may be forwarded to the service: all but Host
+/// and hop-by-hop headers, narrowed by the service's `forward_headers` allow
+/// or deny list.
+fn client_headers(incoming: &HeaderMap, service: &ServiceConfig) -> HeaderMap {
+    let mut headers = incoming.clone();
+    headers.remove(header::HOST);
+
+    // Headers named in Connection apply to this hop only, like Connection itself
+    let connection_listed: Vec<String> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_string())
+        .collect();
+    for name in connection_listed {
+        headers.remove(name.as_str());
+    }
+    let hop_by_hop: Vec<HeaderName> = headers
+        .keys()
+        .filter(|name| is_hop_by_hop(name.as_str()))
+        .cloned()
+        .collect();
+    for name in hop_by_hop {
+        headers.remove(name);
+    }
+
+    let Some(filter) = &service.forward_headers else {
+        return headers;
+    };
+    let listed = |names: &[String], name: &HeaderName| {
+        names.iter().any(|n| n.eq_ignore_ascii_case(name.as_str()))
+    };
+    if let Some(allow) = &filter.allow {
+        let dropped: Vec<HeaderName> = headers
+            .keys()
+            .filter(|name| !listed(allow, name))
+            .cloned()
+            .collect();
+        for name in dropped {
+            headers.remove(name);
+        }
+    }
+    if let Some(deny) = &filter.deny {
+        for name in deny {
+            headers.remove(name.as_str());
+        }
+    }
+    headers
 }
 
 /// Convert a reqwest response into an axum response, streaming when appropriate.
+///
+/// Upstream error statuses (4xx/5xx) are not proxy errors: they are passed
+/// through with their original status, body, and end-to-end headers so clients
+/// see the provider's own error message.
+///
+/// With a `transform`, bodies are translated back to the client's API shape;
+/// error bodies are translated too.
+///
+/// Received bytes are counted before translation; a streamed body counts each
+/// chunk as it passes. A `capture` gets the upstream's response as received,
+/// without a streamed body.
 async fn convert_response(
     upstream_response: reqwest::Response,
+    stream_content_types: &[String],
+    max_response_bytes: usize,
+    transform: Option<Transform>,
+    byte_counters: ByteCounters,
+    capture: Option<Capture>,
 ) -> std::result::Result<Response<Body>, ProxyError> {
     let status = upstream_response.status();
     let headers = upstream_response.headers().clone();
 
-    // Detect SSE streaming responses
-    let is_streaming = headers
-        .get("content-type")
-        .and_then(|v| v.to_str().ok())
-        .map(|v| v.contains("text/event-stream"))
-        .unwrap_or(false);
-
-    let body = if is_streaming {
-        // Stream SSE responses chunk by chunk
-        let stream = upstream_response
-            .bytes_stream()
-            .map(|result| result.map_err(|e| axum::Error::new(e)));
-        Body::from_stream(stream)
+    if let (Some(_), Some(encoding)) = (transform, compressed_encoding(&headers)) {
+        let e = ProxyError::UpstreamRequest(format!(
+            "Upstream response is {}-encoded and can't be translated",
+            encoding
+        ));
+        if let Some(capture) = capture {
+            capture.fail(&e);
+        }
+        return Err(e);
+    }
+
+    // A translated body is only streamed if it's SSE; other bodies are
+    // translated whole, even when the upstream sent them chunked
+    let streaming = match transform {
+        Some(_) => is_event_stream(&headers),
+        None => is_streaming_response(&headers, stream_content_types),
+    };
+    let body = if streaming {
+        if let Some(capture) = capture {
+            capture.finish(status, &headers, None);
+        }
+        // Stream the response chunk by chunk
+        let stream = upstream_response.bytes_stream().map(move |result| {
+            if let Ok(chunk) = &result {
+                byte_counters.add_received(chunk.len());
+            }
+            result.map_err(|e| axum::Error::new(e.without_url()))
+        });
+        match transform {
+            Some(transform) => {
+                let mut translator = transform::StreamTranslator::new(transform);
+                Body::from_stream(stream.map(move |chunk| {
+                    chunk.map(|chunk| axum::body::Bytes::from(translator.push(&chunk)))
+                }))
+            }
+            None => Body::from_stream(stream),
+        }
     } else {
-        // Buffer non-streaming responses
-        let bytes = upstream_response
-            .bytes()
-            .await
-            .map_err(|e| ProxyError::UpstreamRequest(e.to_string()))?;
-        Body::from(bytes)
+        // Buffer non-streaming responses, up to the size limit
+        let body = match read_limited(upstream_response, max_response_bytes).await {
+            Ok(body) => body,
+            Err(e) => {
+                if let Some(capture) = capture {
+                    capture.fail(&e);
+                }
+                return Err(e);
+            }
+        };
+        byte_counters.add_received(body.len());
+        if let Some(capture) = capture {
+            capture.finish(status, &headers, Some(body.as_slice()));
+        }
+        match transform {
+            Some(transform) => Body::from(transform::translate_response(transform, &body)),
+            None => Body::from(body),
+        }
     };
 
     let mut builder = Response::builder().status(
         StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
     );
 
-    // Copy response headers, skipping hop-by-hop headers
+    // Copy response headers, skipping hop-by-hop headers. A translated body
+    // has its own length.
     for (name, value) in headers.iter() {
-        if !is_hop_by_hop(name.as_str()) {
+        let translated_length = transform.is_some() && name == header::CONTENT_LENGTH;
+        if !is_hop_by_hop(name.as_str()) && !translated_length {
             builder = builder.header(name, value);
         }
     }
@@ -185,6 +1051,87 @@ async fn convert_response(
         .map_err(|e| ProxyError::UpstreamRequest(e.to_string()))
 }
 
+/// Read a whole upstream response body, failing once it exceeds `limit` bytes
+/// rather than buffering an arbitrarily large body.
+async fn read_limited(
+    upstream_response: reqwest::Response,
+    limit: usize,
+) -> std::result::Result<Vec<u8>, ProxyError> {
+    let too_large =
+        || ProxyError::UpstreamResponseTooLarge(format!("response body exceeds {} bytes", limit));
+
+    if upstream_response
+        .content_length()
+        .is_some_and(|len| len > limit as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    let mut stream = upstream_response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| ProxyError::UpstreamRequest(e.without_url().to_string()))?;
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Extra content types to stream for `service`: its own list if it has one,
+/// else the global `upstream.stream_content_types`.
+fn stream_content_types<'a>(config: &'a Config, service: &'a ServiceConfig) -> &'a [String] {
+    service
+        .stream_content_types
+        .as_deref()
+        .unwrap_or(&config.upstream.stream_content_types)
+}
+
+/// Whether an upstream response should be streamed to the client rather than
+/// buffered: SSE, a chunked body, or one of the service's extra content types.
+fn is_streaming_response(headers: &HeaderMap, stream_content_types: &[String]) -> bool {
+    let chunked = headers
+        .get_all(header::TRANSFER_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.split(',').any(|coding| coding.trim().eq_ignore_ascii_case("chunked")));
+    if chunked {
+        return true;
+    }
+
+    let Some(media_type) = media_type(headers) else {
+        return false;
+    };
+    media_type.eq_ignore_ascii_case("text/event-stream")
+        || stream_content_types
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(media_type))
+}
+
+/// Whether an upstream response is a server-sent event stream
+fn is_event_stream(headers: &HeaderMap) -> bool {
+    media_type(headers).is_some_and(|t| t.eq_ignore_ascii_case("text/event-stream"))
+}
+
+/// The media type of a response's Content-Type, without parameters
+fn media_type(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(str::trim)
+}
+
+/// The content coding of a response body, if it isn't `identity`
+fn compressed_encoding(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty() && !v.eq_ignore_ascii_case("identity"))
+}
+
 /// Returns true for hop-by-hop headers that should not be forwarded.
 fn is_hop_by_hop(header: &str) -> bool {
     matches!(
@@ -193,6 +1140,7 @@ fn is_hop_by_hop(header: &str) -> bool {
             | "keep-alive"
             | "proxy-authenticate"
             | "proxy-authorization"
+            | "proxy-connection"
             | "te"
             | "trailers"
             | "transfer-encoding"
@@ -227,3 +1175,1761 @@ async fn shutdown_signal() {
 
     tracing::info!("Shutdown signal received");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ForwardHeaders;
+    use crate::test_logs::{capture_logs, Captured};
+
+    fn test_service() -> ServiceConfig {
+        ServiceConfig {
+            prefix: "/openai".to_string(),
+            upstream: "https://api.openai.com".to_string(),
+            secret: "openai".to_string(),
+            auth_header: "Authorization".to_string(),
+            auth_format: "Bearer {secret}".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Serve the proxy on an ephemeral port with a single service pointing at `upstream`.
+    async fn spawn_proxy(upstream: &str) -> String {
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.to_string(),
+                ..test_service()
+            },
+        );
+        spawn_proxy_with(config).await
+    }
+
+    #[tokio::test]
+    async fn test_run_with_listener_serves_requests() {
+        use wiremock::matchers::{header as header_eq, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .and(header_eq("authorization", "Bearer sk-test"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("models"))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                ..test_service()
+            },
+        );
+        let secrets = SecretStore::new();
+        secrets.insert("openai", "sk-test");
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(ProxyServer::new(config, secrets).run_with_listener(listener));
+
+        let response = reqwest::get(format!("http://{}/openai/v1/models", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "models");
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_run_with_listeners_serves_each() {
+        let first = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addrs = [first.local_addr().unwrap(), second.local_addr().unwrap()];
+        let server = ProxyServer::new(Config::default(), SecretStore::new());
+        let server = tokio::spawn(server.run_with_listeners(vec![first, second]));
+
+        for addr in addrs {
+            let response = reqwest::get(format!("http://{}{}", addr, STATS_PATH)).await.unwrap();
+            assert_eq!(response.status(), 200);
+        }
+        server.abort();
+    }
+
+    #[test]
+    fn test_startup_summary_redacts_secrets() {
+        let mut config = Config::default();
+        config.services.insert("openai".to_string(), test_service());
+        let secrets = SecretStore::new();
+        secrets.insert("openai", "sk-live-abcdef123456");
+
+        let ((), output) = capture_logs(|| log_startup_summary(&config, &secrets));
+        assert!(output.contains("Effective configuration"));
+        assert!(output.contains("listen=127.0.0.1:8080"));
+        assert!(output.contains("openai /openai -> https://api.openai.com"));
+        assert!(output.contains("secrets_loaded=openai"));
+        assert!(!output.contains("sk-live-abcdef123456"));
+    }
+
+    /// Serve the proxy on an ephemeral port with the given config and an `openai` secret.
+    async fn spawn_proxy_with(config: Config) -> String {
+        let secrets = SecretStore::new();
+        secrets.insert("openai", "sk-test");
+
+        let state = AppState::new(config, secrets).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, build_router(state)).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    /// Send a raw CONNECT request and return the status code and body.
+    async fn send_connect(proxy: &str, target: &str) -> (u16, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let addr = proxy.trim_start_matches("http://");
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request =
+            format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+        let body = response.split("\r\n\r\n").nth(1).unwrap_or_default().to_string();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_configured_host() {
+        let mut config = Config::default();
+        config.services.insert("openai".to_string(), test_service());
+        let proxy = spawn_proxy_with(config).await;
+
+        let (status, body) = send_connect(&proxy, "api.openai.com:443").await;
+        assert_eq!(status, 501);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["service"], "openai");
+        assert_eq!(body["base_url"], "http://127.0.0.1:8080/openai");
+    }
+
+    #[tokio::test]
+    async fn test_connect_hint_uses_listen_addresses() {
+        let mut config = Config::default();
+        config.listen.addresses = vec!["10.0.0.5:9000".to_string(), "127.0.0.1:9000".to_string()];
+        config.services.insert("openai".to_string(), test_service());
+        let proxy = spawn_proxy_with(config).await;
+
+        let (_, body) = send_connect(&proxy, "api.openai.com:443").await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["base_url"], "http://10.0.0.5:9000/openai");
+    }
+
+    #[tokio::test]
+    async fn test_connect_hint_names_socket() {
+        let mut config = Config::default();
+        config.listen.socket_path = Some(PathBuf::from("/run/clawproxy/proxy.sock"));
+        config.services.insert("openai".to_string(), test_service());
+        let target: Uri = "api.openai.com:443".parse().unwrap();
+
+        let response = connect_response(&config, &target, "req-1");
+        assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["socket_path"], "/run/clawproxy/proxy.sock");
+        assert!(body.get("base_url").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_unknown_host() {
+        let mut config = Config::default();
+        config.services.insert("openai".to_string(), test_service());
+        let proxy = spawn_proxy_with(config).await;
+
+        let (status, _) = send_connect(&proxy, "evil.example.com:443").await;
+        assert_eq!(status, 403);
+    }
+
+    async fn assert_method_forwarded(method_name: &str, body: &'static str) {
+        use wiremock::matchers::{body_string, header as header_eq, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method(method_name))
+            .and(path("/v1/files/file-123"))
+            .and(header_eq("authorization", "Bearer sk-test"))
+            .and(body_string(body))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let proxy = spawn_proxy(&upstream.uri()).await;
+        let response = reqwest::Client::new()
+            .request(
+                reqwest::Method::from_bytes(method_name.as_bytes()).unwrap(),
+                format!("{}/openai/v1/files/file-123", proxy),
+            )
+            .header("authorization", "Bearer PROXY:openai")
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_rejected_with_413() {
+        let proxy = spawn_proxy("https://api.openai.com").await;
+        let response = reqwest::Client::new()
+            .post(format!("{}/openai/v1/files", proxy))
+            .body(vec![b'x'; MAX_REQUEST_BODY_SIZE + 1])
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Request body too large");
+    }
+
+    #[tokio::test]
+    async fn test_encoded_path_and_query_forwarded_unchanged() {
+        use wiremock::matchers::any;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&upstream)
+            .await;
+
+        let proxy = spawn_proxy(&upstream.uri()).await;
+        let response = reqwest::get(format!("{}/openai/v1/files/file%2Fname?q=a%26b", proxy))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let received = upstream.received_requests().await.unwrap();
+        assert_eq!(received[0].url.path(), "/v1/files/file%2Fname");
+        assert_eq!(received[0].url.query(), Some("q=a%26b"));
+    }
+
+    #[test]
+    fn test_is_streaming_response() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in pairs {
+                map.append(*name, HeaderValue::from_static(value));
+            }
+            map
+        };
+        let ndjson = vec!["application/x-ndjson".to_string()];
+
+        assert!(is_streaming_response(
+            &headers(&[("content-type", "text/event-stream; charset=utf-8")]),
+            &[]
+        ));
+        assert!(is_streaming_response(
+            &headers(&[("content-type", "application/json"), ("transfer-encoding", "chunked")]),
+            &[]
+        ));
+        assert!(is_streaming_response(
+            &headers(&[("content-type", "Application/X-NDJSON")]),
+            &ndjson
+        ));
+        assert!(!is_streaming_response(
+            &headers(&[("content-type", "application/x-ndjson")]),
+            &[]
+        ));
+        assert!(!is_streaming_response(
+            &headers(&[("content-type", "application/json"), ("content-length", "2")]),
+            &ndjson
+        ));
+    }
+
+    /// Upstream that sends the response head and the first part of the body,
+    /// then stalls. Only a streaming proxy can pass that first part on.
+    async fn spawn_stalling_upstream(head: &'static str, first_part: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(first_part.as_bytes()).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(30)).await;
+        });
+        format!("http://{}", addr)
+    }
+
+    async fn assert_first_part_streamed(
+        upstream: String,
+        stream_content_types: Option<Vec<String>>,
+        expected: &str,
+    ) {
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream,
+                stream_content_types,
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let mut response = reqwest::get(format!("{}/openai/v1/stream", proxy)).await.unwrap();
+        let chunk = tokio::time::timeout(Duration::from_secs(5), response.chunk())
+            .await
+            .expect("response was buffered instead of streamed")
+            .unwrap()
+            .unwrap();
+        assert_eq!(chunk, expected.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_streamed_when_configured() {
+        let line = "{\"delta\":\"hi\"}\n";
+        let upstream = spawn_stalling_upstream(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nContent-Length: 100\r\n\r\n",
+            line,
+        )
+        .await;
+        assert_first_part_streamed(upstream, Some(vec!["application/x-ndjson".to_string()]), line)
+            .await;
+    }
+
+    #[test]
+    fn test_stream_content_types_precedence() {
+        let mut config = Config::default();
+        config.upstream.stream_content_types = vec!["application/x-ndjson".to_string()];
+        let service = test_service();
+        assert_eq!(stream_content_types(&config, &service), ["application/x-ndjson"]);
+
+        // A service's own list replaces the global one, even when empty
+        let service = ServiceConfig {
+            stream_content_types: Some(vec![]),
+            ..test_service()
+        };
+        assert!(stream_content_types(&config, &service).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_streamed_by_global_config() {
+        let line = "{\"delta\":\"hi\"}\n";
+        let head =
+            "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nContent-Length: 100\r\n\r\n";
+        let mut config = Config::default();
+        config.upstream.stream_content_types = vec!["application/x-ndjson".to_string()];
+        for name in ["ollama", "vllm"] {
+            config.services.insert(
+                name.to_string(),
+                ServiceConfig {
+                    prefix: format!("/{}", name),
+                    upstream: spawn_stalling_upstream(head, line).await,
+                    ..test_service()
+                },
+            );
+        }
+        let proxy = spawn_proxy_with(config).await;
+
+        for name in ["ollama", "vllm"] {
+            let url = format!("{}/{}/api/generate", proxy, name);
+            let mut response = reqwest::get(url).await.unwrap();
+            let chunk = tokio::time::timeout(Duration::from_secs(5), response.chunk())
+                .await
+                .expect("response was buffered instead of streamed")
+                .unwrap()
+                .unwrap();
+            assert_eq!(chunk, line.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunked_response_streamed() {
+        let head = "HTTP/1.1 200 OK\r\n\
+                    Content-Type: application/json\r\n\
+                    Transfer-Encoding: chunked\r\n\r\n";
+        let upstream = spawn_stalling_upstream(head, "5\r\nhello\r\n").await;
+        assert_first_part_streamed(upstream, None, "hello").await;
+    }
+
+    #[tokio::test]
+    async fn test_buffered_response_size_limit() {
+        use wiremock::matchers::path;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(path("/large"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![b'x'; 2048]))
+            .mount(&upstream)
+            .await;
+        Mock::given(path("/small"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![b'x'; 512]))
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.upstream.max_response_bytes = 1024;
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let response = reqwest::get(format!("{}/openai/large", proxy)).await.unwrap();
+        assert_eq!(response.status(), 502);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Upstream response too large");
+
+        let response = reqwest::get(format!("{}/openai/small", proxy)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.bytes().await.unwrap().len(), 512);
+    }
+
+    /// "hello", gzip-compressed
+    const GZIP_HELLO: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x07, 0x00, 0x86, 0xa6, 0x10, 0x36, 0x05, 0x00, 0x00, 0x00,
+    ];
+
+    async fn fetch_gzip_through_proxy(decompress_responses: bool) -> reqwest::Response {
+        use wiremock::matchers::{header, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(path("/v1/data"))
+            .and(header("accept-encoding", "gzip"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_raw(GZIP_HELLO, "text/plain"),
+            )
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.upstream.decompress_responses = decompress_responses;
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        // The test client mustn't decompress either, so it sees what the proxy sent
+        reqwest::Client::builder()
+            .no_gzip()
+            .build()
+            .unwrap()
+            .get(format!("{}/openai/v1/data", proxy))
+            .header("accept-encoding", "gzip")
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_compressed_response_passed_through() {
+        let response = fetch_gzip_through_proxy(false).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        assert_eq!(response.bytes().await.unwrap().as_ref(), GZIP_HELLO);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_response_decompressed_when_enabled() {
+        let response = fetch_gzip_through_proxy(true).await;
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().get("content-encoding").is_none());
+        assert_eq!(response.text().await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_body_passed_through() {
+        use wiremock::matchers::any;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let error_body = r#"{"error":{"message":"Invalid model","type":"invalid_request_error"}}"#;
+        let upstream = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .insert_header("x-request-id", "req-upstream")
+                    .set_body_raw(error_body, "application/json"),
+            )
+            .mount(&upstream)
+            .await;
+
+        let proxy = spawn_proxy(&upstream.uri()).await;
+        let response = reqwest::Client::new()
+            .post(format!("{}/openai/v1/chat/completions", proxy))
+            .body("{}")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 400);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(response.headers()["x-request-id"], "req-upstream");
+        assert_eq!(response.text().await.unwrap(), error_body);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_server_error_passed_through() {
+        use wiremock::matchers::any;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503).set_body_string("overloaded"))
+            .mount(&upstream)
+            .await;
+
+        let proxy = spawn_proxy(&upstream.uri()).await;
+        let response = reqwest::get(format!("{}/openai/v1/models", proxy))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.text().await.unwrap(), "overloaded");
+    }
+
+    #[tokio::test]
+    async fn test_routes_absolute_form_request_by_host() {
+        use wiremock::matchers::{header, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(path("/v1/models"))
+            .and(header("authorization", "Bearer sk-test"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                match_host: Some("api.openai.com".to_string()),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        // A client configured with HTTP_PROXY sends an absolute-form request line
+        let client = reqwest::Client::builder()
+            .proxy(reqwest::Proxy::http(&proxy).unwrap())
+            .build()
+            .unwrap();
+        let response = client
+            .get("http://api.openai.com/v1/models")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_returned_and_forwarded() {
+        use wiremock::matchers::any;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&upstream)
+            .await;
+
+        let proxy = spawn_proxy(&upstream.uri()).await;
+        let response = reqwest::get(format!("{}/openai/v1/models", proxy))
+            .await
+            .unwrap();
+
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        assert!(Uuid::parse_str(&request_id).is_ok());
+
+        // The same ID was forwarded upstream
+        let received = upstream.received_requests().await.unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].headers[REQUEST_ID_HEADER], request_id.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_request_id_reuses_client_value() {
+        use wiremock::matchers::{any, header as header_eq};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(any())
+            .and(header_eq(REQUEST_ID_HEADER, "agent-run-42"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let proxy = spawn_proxy(&upstream.uri()).await;
+        let response = reqwest::Client::new()
+            .get(format!("{}/openai/v1/models", proxy))
+            .header(REQUEST_ID_HEADER, "agent-run-42")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "agent-run-42");
+    }
+
+    #[tokio::test]
+    async fn test_request_id_in_error_body() {
+        let proxy = spawn_proxy("http://127.0.0.1:1").await;
+        let response = reqwest::get(format!("{}/unknown/path", proxy)).await.unwrap();
+
+        assert_eq!(response.status(), 404);
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["request_id"], request_id.as_str());
+        assert_eq!(body["error"], "Unknown service");
+    }
+
+    #[tokio::test]
+    async fn test_forwards_patch_with_body() {
+        assert_method_forwarded("PATCH", r#"{"purpose":"fine-tune"}"#).await;
+    }
+
+    #[tokio::test]
+    async fn test_forwards_delete_with_body() {
+        assert_method_forwarded("DELETE", r#"{"force":true}"#).await;
+    }
+
+    #[tokio::test]
+    async fn test_forwards_delete_without_body() {
+        assert_method_forwarded("DELETE", "").await;
+    }
+
+    #[tokio::test]
+    async fn test_forwards_non_standard_method() {
+        assert_method_forwarded("PURGE", "").await;
+    }
+
+    #[tokio::test]
+    async fn test_long_request_does_not_block_shutdown() {
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(wiremock::matchers::any())
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                ..test_service()
+            },
+        );
+        let secrets = SecretStore::new();
+        secrets.insert("openai", "sk-test");
+        let state = AppState::new(config, secrets).unwrap();
+        let active_requests = state.active_requests.clone();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve_with_drain_timeout(
+            Listener::Tcp(listener),
+            state,
+            async move {
+                let _ = stop_rx.await;
+            },
+            Duration::from_millis(300),
+        ));
+
+        // Start a request that will be stuck waiting on the slow upstream
+        tokio::spawn(async move {
+            let _ = reqwest::get(format!("http://{}/openai/v1/models", addr)).await;
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(active_requests.load(Ordering::SeqCst), 1);
+
+        stop_tx.send(()).unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(3), server)
+            .await
+            .expect("shutdown should not wait for the stuck request");
+        assert!(result.unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_reload_swaps_config_and_secrets() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let secrets_dir = dir.path().join("secrets");
+        std::fs::create_dir(&secrets_dir).unwrap();
+        std::fs::write(secrets_dir.join("reloaded"), "sk-reloaded").unwrap();
+        let config_path = dir.path().join("config.yaml");
+        std::fs::write(
+            &config_path,
+            format!(
+                r#"
+listen:
+  port: 8080
+secrets_dir: "{}"
+services:
+  reloaded:
+    prefix: "/reloaded"
+    upstream: "https://reloaded.example.com"
+    secret: "reloaded"
+    auth_header: "Authorization"
+    auth_format: "Bearer {{secret}}"
+"#,
+                secrets_dir.display()
+            ),
+        )
+        .unwrap();
+
+        let secrets = SecretStore::new();
+        secrets.insert("openai", "sk-old");
+        let mut config = Config::default();
+        config.services.insert("openai".to_string(), test_service());
+        let state = AppState::new(config, secrets).unwrap();
+
+        reload(&state, Some(&config_path)).unwrap();
+
+        let config = state.config();
+        assert!(config.services.contains_key("reloaded"));
+        assert!(!config.services.contains_key("openai"));
+        assert_eq!(state.secrets.get("reloaded").as_deref(), Some("sk-reloaded"));
+        assert!(state.secrets.get("openai").is_none());
+    }
+
+    #[test]
+    fn test_reload_failure_keeps_config() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config_path = dir.path().join("config.yaml");
+        std::fs::write(&config_path, "not: [valid").unwrap();
+
+        let mut config = Config::default();
+        config.services.insert("openai".to_string(), test_service());
+        let state = AppState::new(config, SecretStore::new()).unwrap();
+
+        assert!(reload(&state, Some(&config_path)).is_err());
+        assert!(state.config().services.contains_key("openai"));
+    }
+
+    #[test]
+    fn test_upstream_headers_keep_multi_valued() {
+        let mut incoming = HeaderMap::new();
+        incoming.append("accept", HeaderValue::from_static("application/json"));
+        incoming.append("accept", HeaderValue::from_static("text/event-stream"));
+
+        let headers = build_upstream_headers(&incoming, &test_service(), "Bearer sk-1").unwrap();
+        let values: Vec<_> = headers.get_all("accept").iter().collect();
+        assert_eq!(values, vec!["application/json", "text/event-stream"]);
+    }
+
+    #[test]
+    fn test_upstream_headers_replace_mixed_case_auth() {
+        let mut incoming = HeaderMap::new();
+        incoming.append("AUTHORIZATION", HeaderValue::from_static("Bearer PROXY:openai"));
+        incoming.append("authorization", HeaderValue::from_static("Bearer other"));
+        incoming.insert("host", HeaderValue::from_static("127.0.0.1:8080"));
+
+        let mut service = test_service();
+        service.auth_header = "AuThOrIzAtIoN".to_string();
+
+        let headers = build_upstream_headers(&incoming, &service, "Bearer sk-1").unwrap();
+        let values: Vec<_> = headers.get_all("authorization").iter().collect();
+        assert_eq!(values, vec!["Bearer sk-1"]);
+        assert!(headers.get("host").is_none());
+    }
+
+    #[test]
+    fn test_upstream_headers_allowlist() {
+        let mut incoming = HeaderMap::new();
+        incoming.insert("content-type", HeaderValue::from_static("application/json"));
+        incoming.insert("x-internal-trace", HeaderValue::from_static("abc"));
+        incoming.insert("authorization", HeaderValue::from_static("Bearer PROXY:openai"));
+
+        let mut service = test_service();
+        service.forward_headers = Some(ForwardHeaders {
+            allow: Some(vec!["Content-Type".to_string()]),
+            deny: None,
+        });
+
+        let headers = build_upstream_headers(&incoming, &service, "Bearer sk-1").unwrap();
+        assert_eq!(headers.get("content-type").unwrap(), "application/json");
+        assert!(headers.get("x-internal-trace").is_none());
+        // The injected auth header is added even though it isn't listed
+        assert_eq!(headers.get("authorization").unwrap(), "Bearer sk-1");
+        assert_eq!(headers.len(), 2);
+    }
+
+    #[test]
+    fn test_upstream_headers_denylist() {
+        let mut incoming = HeaderMap::new();
+        incoming.insert("content-type", HeaderValue::from_static("application/json"));
+        incoming.insert("x-internal-trace", HeaderValue::from_static("abc"));
+
+        let mut service = test_service();
+        service.forward_headers = Some(ForwardHeaders {
+            allow: None,
+            deny: Some(vec!["X-Internal-Trace".to_string()]),
+        });
+
+        let headers = build_upstream_headers(&incoming, &service, "Bearer sk-1").unwrap();
+        assert_eq!(headers.get("content-type").unwrap(), "application/json");
+        assert!(headers.get("x-internal-trace").is_none());
+        assert_eq!(headers.get("authorization").unwrap(), "Bearer sk-1");
+    }
+
+    #[test]
+    fn test_upstream_headers_drop_hop_by_hop() {
+        let mut incoming = HeaderMap::new();
+        incoming.insert("connection", HeaderValue::from_static("close, x-hop"));
+        incoming.insert("x-hop", HeaderValue::from_static("1"));
+        incoming.insert("proxy-authorization", HeaderValue::from_static("Basic abc"));
+        incoming.insert("proxy-connection", HeaderValue::from_static("keep-alive"));
+        incoming.insert("upgrade", HeaderValue::from_static("websocket"));
+        incoming.insert("accept", HeaderValue::from_static("application/json"));
+
+        let headers = build_upstream_headers(&incoming, &test_service(), "Bearer sk-1").unwrap();
+        let mut names: Vec<_> = headers.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["accept", "authorization"]);
+    }
+
+    #[tokio::test]
+    async fn test_hop_by_hop_request_headers_not_forwarded() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let proxy = spawn_proxy(&upstream.uri()).await;
+
+        let response = reqwest::Client::new()
+            .get(format!("{}/openai/v1/models", proxy))
+            .header("connection", "close")
+            .header("proxy-authorization", "Basic dXNlcjpwYXNz")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let requests = upstream.received_requests().await.unwrap();
+        assert!(requests[0].headers.get("connection").is_none());
+        assert!(requests[0].headers.get("proxy-authorization").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rewritten_body_sends_correct_length() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "msg_01",
+                "model": "claude-sonnet-4-5",
+                "content": [],
+                "usage": { "input_tokens": 1, "output_tokens": 1 },
+            })))
+            .mount(&upstream)
+            .await;
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                transform: Some(Transform::OpenaiToAnthropic),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        // The client's Content-Length is for the body before translation
+        let body = r#"{"model":"claude-sonnet-4-5","messages":[{"role":"user","content":"Hi"}]}"#;
+        reqwest::Client::new()
+            .post(format!("{}/openai/v1/chat/completions", proxy))
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+
+        let received = upstream.received_requests().await.unwrap();
+        assert_eq!(received.len(), 1);
+        assert_ne!(received[0].body.len(), body.len());
+        assert_eq!(
+            received[0].headers[header::CONTENT_LENGTH.as_str()],
+            received[0].body.len().to_string().as_str()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openai_to_anthropic_transform() {
+        use wiremock::matchers::{body_json, header as header_eq, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(header_eq("anthropic-version", "2023-06-01"))
+            .and(body_json(json!({
+                "model": "claude-sonnet-4-5",
+                "system": "Be brief.",
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "max_tokens": 4096,
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "msg_01",
+                "type": "message",
+                "model": "claude-sonnet-4-5",
+                "content": [{ "type": "text", "text": "Hello!" }],
+                "stop_reason": "end_turn",
+                "usage": { "input_tokens": 5, "output_tokens": 2 },
+            })))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                transform: Some(Transform::OpenaiToAnthropic),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/openai/v1/chat/completions", proxy))
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [
+                    { "role": "system", "content": "Be brief." },
+                    { "role": "user", "content": "Hi" },
+                ],
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"]["content"], "Hello!");
+        assert_eq!(body["usage"]["total_tokens"], 7);
+
+        let invalid = reqwest::Client::new()
+            .post(format!("{}/openai/v1/chat/completions", proxy))
+            .body("not json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(invalid.status(), 400);
+    }
+
+    /// An Anthropic messages response saying "Hello!"
+    fn anthropic_hello() -> serde_json::Value {
+        json!({
+            "id": "msg_01",
+            "type": "message",
+            "model": "claude-sonnet-4-5",
+            "content": [{ "type": "text", "text": "Hello!" }],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 5, "output_tokens": 2 },
+        })
+    }
+
+    async fn post_chat_completion(proxy: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/openai/v1/chat/completions", proxy))
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .json(&json!({
+                "model": "claude-sonnet-4-5",
+                "messages": [{ "role": "user", "content": "Hi" }],
+            }))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    async fn spawn_transform_proxy(upstream: String) -> String {
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream,
+                transform: Some(Transform::OpenaiToAnthropic),
+                ..test_service()
+            },
+        );
+        spawn_proxy_with(config).await
+    }
+
+    #[tokio::test]
+    async fn test_transform_asks_for_uncompressed_response() {
+        use wiremock::matchers::{header as header_eq, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        // Compresses unless asked not to
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header_eq("accept-encoding", "identity"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(anthropic_hello()))
+            .with_priority(1)
+            .mount(&upstream)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_bytes(GZIP_HELLO),
+            )
+            .mount(&upstream)
+            .await;
+        let proxy = spawn_transform_proxy(upstream.uri()).await;
+
+        let response = post_chat_completion(&proxy).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["choices"][0]["message"]["content"], "Hello!");
+    }
+
+    #[tokio::test]
+    async fn test_transform_rejects_compressed_response() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        // Compresses regardless of Accept-Encoding
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_bytes(GZIP_HELLO),
+            )
+            .mount(&upstream)
+            .await;
+        let proxy = spawn_transform_proxy(upstream.uri()).await;
+
+        let response = post_chat_completion(&proxy).await;
+        assert_eq!(response.status(), 502);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Upstream error");
+    }
+
+    #[tokio::test]
+    async fn test_transform_translates_chunked_json() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let body = anthropic_hello().to_string();
+            let (first, second) = body.split_at(body.len() / 2);
+            let mut response = "HTTP/1.1 200 OK\r\n\
+                                Content-Type: application/json\r\n\
+                                Transfer-Encoding: chunked\r\n\r\n"
+                .to_string();
+            for part in [first, second, ""] {
+                response.push_str(&format!("{:x}\r\n{}\r\n", part.len(), part));
+            }
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+        let proxy = spawn_transform_proxy(format!("http://{}", addr)).await;
+
+        let response = post_chat_completion(&proxy).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["object"], "chat.completion");
+        assert_eq!(body["choices"][0]["message"]["content"], "Hello!");
+    }
+
+    #[tokio::test]
+    async fn test_path_cannot_escape_upstream_host() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&upstream)
+            .await;
+        let proxy = spawn_proxy(&upstream.uri()).await;
+        let client = reqwest::Client::new();
+
+        // An empty segment stays on the configured host. Dot segments are
+        // normalized by the client, see the router tests.
+        let response = client.get(format!("{}/openai//evil.com", proxy)).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        for path in ["/openai@evil.com/v1/models", "/openai.evil.com/v1/models"] {
+            let response = client.get(format!("{}{}", proxy, path)).send().await.unwrap();
+            assert_eq!(response.status(), 400, "{}", path);
+        }
+        let requests = upstream.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dot_segments_resolved_within_service() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+        let proxy = spawn_proxy(&upstream.uri()).await;
+
+        // reqwest would resolve the dot segments itself, so send them raw
+        let status = |target: &'static str| {
+            let addr = proxy.trim_start_matches("http://").to_string();
+            async move {
+                let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+                let request =
+                    format!("GET {target} HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response.split_whitespace().nth(1).unwrap().to_string()
+            }
+        };
+
+        assert_eq!(status("/openai/v1/files/../models").await, "200");
+        assert_eq!(status("/openai/v1/../../admin").await, "400");
+        assert_eq!(status("/openai/v1/%2e%2e/%2E%2E/admin").await, "400");
+    }
+
+    #[tokio::test]
+    async fn test_path_filters() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                allow_paths: Some(vec![
+                    "/v1/chat/completions".to_string(),
+                    "/v1/files".to_string(),
+                ]),
+                deny_paths: Some(vec!["/v1/files".to_string()]),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let response = reqwest::get(format!("{}/openai/v1/chat/completions", proxy)).await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        for path in [
+            "/openai/v1/files",
+            "/openai/v1/files/file-1",
+            "/openai/v1/models",
+            "/openai/v1//files",
+            "/openai//v1/files",
+            "/openai/v1/files;x",
+        ] {
+            let response = reqwest::get(format!("{}{}", proxy, path)).await.unwrap();
+            assert_eq!(response.status(), 403, "{}", path);
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["error"], "Path not allowed for this service");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_in_query_injects_parameter() {
+        use wiremock::matchers::{method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/search"))
+            .and(query_param("q", "rust"))
+            .and(query_param("key", "sk-test"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                auth_format: "{secret}".to_string(),
+                auth_in: AuthLocation::Query,
+                auth_param: Some("key".to_string()),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let response = reqwest::get(format!("{}/openai/v1/search?q=rust&key=PROXY:openai", proxy))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        // No auth header is injected for query services
+        let requests = upstream.received_requests().await.unwrap();
+        assert!(requests[0].headers.get("authorization").is_none());
+        assert_eq!(requests[0].url.query(), Some("q=rust&key=sk-test"));
+    }
+
+    fn prepare(
+        config: &Config,
+        request: &Request<()>,
+    ) -> std::result::Result<UpstreamRequest, ProxyError> {
+        let secrets = SecretStore::new();
+        secrets.insert("openai", "sk-test");
+        let patterns = PathPatterns::from_services(&config.services).unwrap();
+        prepare_upstream_request(config, &patterns, &secrets, request, "req-1")
+    }
+
+    #[test]
+    fn test_prepare_upstream_request_injects_credential() {
+        let mut config = Config::default();
+        config.services.insert("openai".to_string(), test_service());
+
+        let request = Request::post("/openai/v1/chat/completions?stream=true")
+            .header("authorization", "Bearer PROXY:openai")
+            .header("content-type", "application/json")
+            .body(())
+            .unwrap();
+        let upstream = prepare(&config, &request).unwrap();
+
+        assert_eq!(upstream.service, "openai");
+        assert_eq!(upstream.method, Method::POST);
+        assert_eq!(upstream.url, "https://api.openai.com/v1/chat/completions?stream=true");
+        assert_eq!(upstream.headers.get("authorization").unwrap(), "Bearer sk-test");
+        assert_eq!(upstream.headers.get("content-type").unwrap(), "application/json");
+        assert_eq!(upstream.headers.get(REQUEST_ID_HEADER).unwrap(), "req-1");
+    }
+
+    #[test]
+    fn test_prepare_upstream_request_query_credential() {
+        let mut config = Config::default();
+        let service = ServiceConfig {
+            auth_format: "{secret}".to_string(),
+            auth_in: AuthLocation::Query,
+            auth_param: Some("key".to_string()),
+            ..test_service()
+        };
+        config.services.insert("openai".to_string(), service);
+
+        let request = Request::get("/openai/v1/models").body(()).unwrap();
+        let upstream = prepare(&config, &request).unwrap();
+
+        assert_eq!(upstream.url, "https://api.openai.com/v1/models?key=sk-test");
+        assert_eq!(upstream.logged_url, "https://api.openai.com/v1/models");
+        assert!(upstream.headers.get("authorization").is_none());
+    }
+
+    #[test]
+    fn test_prepare_upstream_request_errors() {
+        let mut config = Config::default();
+        config.services.insert("openai".to_string(), test_service());
+
+        let request = Request::get("/unknown/v1").body(()).unwrap();
+        let err = prepare(&config, &request).unwrap_err();
+        assert!(matches!(err, ProxyError::UnknownService(_)));
+
+        // The service's secret isn't loaded
+        let service = ServiceConfig {
+            secret: "missing".to_string(),
+            ..test_service()
+        };
+        config.services.insert("openai".to_string(), service);
+        let request = Request::get("/openai/v1/models").body(()).unwrap();
+        let (result, logs) = capture_logs(|| prepare(&config, &request));
+        assert!(matches!(result, Err(ProxyError::SecretUnavailable(_))));
+        assert!(logs.contains("Secret for matched service is not loaded"));
+        assert!(logs.contains("secret=\"missing\""));
+    }
+
+    #[tokio::test]
+    async fn test_missing_secret_returns_503() {
+        let mut config = Config::default();
+        let service = ServiceConfig {
+            secret: "missing".to_string(),
+            ..test_service()
+        };
+        config.services.insert("openai".to_string(), service);
+        let proxy = spawn_proxy_with(config).await;
+
+        let response = reqwest::get(format!("{}/openai/v1/models", proxy)).await.unwrap();
+        assert_eq!(response.status(), 503);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Service temporarily misconfigured");
+    }
+
+    #[tokio::test]
+    async fn test_unresolvable_upstream_logs_host() {
+        let captured = Captured::default();
+        // The current-thread test runtime polls the proxy's tasks here, under this subscriber
+        let _guard = tracing::subscriber::set_default(captured.subscriber());
+
+        let proxy = spawn_proxy("https://clawproxy-test.invalid").await;
+        let response = reqwest::get(format!("{}/openai/v1/models", proxy)).await.unwrap();
+        assert_eq!(response.status(), 502);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "Upstream host not found");
+
+        let output = captured.output();
+        assert!(output.contains("Could not resolve upstream host 'clawproxy-test.invalid'"));
+        assert!(!output.contains("sk-test"));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_pass_through() {
+        use axum::extract::ws::{Message, WebSocketUpgrade};
+        use futures::SinkExt;
+        use tokio_tungstenite::tungstenite;
+
+        // Greets with the auth header it was sent, then echoes text messages
+        let upstream = Router::new().route(
+            "/v1/realtime",
+            get(|ws: WebSocketUpgrade, headers: HeaderMap| async move {
+                let auth = headers
+                    .get("authorization")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                ws.on_upgrade(move |mut socket| async move {
+                    let _ = socket.send(Message::Text(format!("auth: {}", auth))).await;
+                    while let Some(Ok(Message::Text(text))) = socket.recv().await {
+                        let _ = socket.send(Message::Text(format!("echo: {}", text))).await;
+                    }
+                })
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, upstream).await.unwrap();
+        });
+
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: format!("http://{}", addr),
+                websocket: true,
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let url = format!("{}/openai/v1/realtime", proxy.replacen("http", "ws", 1));
+        let (mut socket, response) = tokio_tungstenite::connect_async(url).await.unwrap();
+        assert_eq!(response.status(), 101);
+
+        let greeting = socket.next().await.unwrap().unwrap();
+        assert_eq!(greeting, tungstenite::Message::Text("auth: Bearer sk-test".into()));
+        socket.send(tungstenite::Message::Text("hello".into())).await.unwrap();
+        let echo = socket.next().await.unwrap().unwrap();
+        assert_eq!(echo, tungstenite::Message::Text("echo: hello".into()));
+        socket.close(None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mock_response_skips_upstream() {
+        use wiremock::MockServer;
+
+        // Nothing is mounted, so any request reaching the upstream would 404
+        let upstream = MockServer::start().await;
+
+        // A relative body_file is read from the loaded config's directory
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("models.json"), r#"{"data":[]}"#).unwrap();
+
+        let mut config = Config {
+            location: dir.path().to_path_buf(),
+            ..Config::default()
+        };
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                // No secret is loaded for this service
+                secret: "missing".to_string(),
+                mock_response: Some(MockResponse {
+                    status: 201,
+                    content_type: "application/json".to_string(),
+                    body: None,
+                    body_file: Some(PathBuf::from("models.json")),
+                }),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let response = reqwest::get(format!("{}/openai/v1/models", proxy)).await.unwrap();
+        assert_eq!(response.status(), 201);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert_eq!(response.text().await.unwrap(), r#"{"data":[]}"#);
+        assert!(upstream.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mock_response_inline_body() {
+        let mock = MockResponse {
+            status: 200,
+            content_type: "text/plain".to_string(),
+            body: Some("hello".to_string()),
+            body_file: None,
+        };
+        let response = mock_response(&mock, &Config::default()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_mock_response_missing_body_file() {
+        let mock = MockResponse {
+            status: 200,
+            content_type: "application/json".to_string(),
+            body: None,
+            body_file: Some(PathBuf::from("/nonexistent/clawproxy-mock.json")),
+        };
+        let err = mock_response(&mock, &Config::default()).await.unwrap_err();
+        assert!(matches!(err, ProxyError::MockResponse(_)));
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "Mock response unavailable");
+    }
+
+    async fn fetch_stats(proxy: &str) -> serde_json::Value {
+        let response = reqwest::get(format!("{}{}", proxy, STATS_PATH)).await.unwrap();
+        response.json().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stats_path_does_not_shadow_services() {
+        let mut config = Config::default();
+        config.services.insert(
+            "stats".to_string(),
+            ServiceConfig {
+                prefix: "/stats".to_string(),
+                mock_response: Some(MockResponse {
+                    status: 200,
+                    content_type: "text/plain".to_string(),
+                    body: Some("from service".to_string()),
+                    body_file: None,
+                }),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let response = reqwest::get(format!("{}/stats", proxy)).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "from service");
+        assert!(fetch_stats(&proxy).await["services"].is_object());
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_requests() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/models"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&upstream)
+            .await;
+        let proxy = spawn_proxy(&upstream.uri()).await;
+
+        let stats = fetch_stats(&proxy).await;
+        assert_eq!(stats["services"], json!({}));
+
+        for path in ["/openai/v1/models", "/openai/v1/missing"] {
+            reqwest::get(format!("{}{}", proxy, path)).await.unwrap();
+        }
+
+        let stats = fetch_stats(&proxy).await;
+        assert_eq!(stats["services"]["openai"]["requests"], 2);
+        assert_eq!(stats["services"]["openai"]["errors"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_status_classes() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        for (route, status) in [("/v1/ok", 200), ("/v1/limited", 429), ("/v1/down", 503)] {
+            Mock::given(method("GET"))
+                .and(path(route))
+                .respond_with(ResponseTemplate::new(status))
+                .mount(&upstream)
+                .await;
+        }
+        let proxy = spawn_proxy(&upstream.uri()).await;
+
+        for route in ["/v1/ok", "/v1/ok", "/v1/limited", "/v1/down"] {
+            reqwest::get(format!("{}/openai{}", proxy, route)).await.unwrap();
+        }
+
+        let stats = fetch_stats(&proxy).await;
+        let status = &stats["services"]["openai"]["status"];
+        assert_eq!(status["2xx"], 2);
+        assert_eq!(status["4xx"], 1);
+        assert_eq!(status["5xx"], 1);
+        assert_eq!(status["3xx"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_capture_dir_records_redacted_exchange() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"id":"chatcmpl-1"}"#))
+            .mount(&upstream)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config {
+            capture_dir: Some(dir.path().join("captures")),
+            ..Default::default()
+        };
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        reqwest::Client::new()
+            .post(format!("{}/openai/v1/chat/completions", proxy))
+            .header(REQUEST_ID_HEADER, "capture-test")
+            .body(r#"{"model":"gpt-4o"}"#)
+            .send()
+            .await
+            .unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(dir.path().join("captures"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let text = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(!text.contains("sk-test"), "{}", text);
+
+        let exchange: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(exchange["request_id"], "capture-test");
+        assert_eq!(exchange["request"]["method"], "POST");
+        assert_eq!(exchange["request"]["headers"]["authorization"], json!(["***"]));
+        assert_eq!(exchange["request"]["body"], r#"{"model":"gpt-4o"}"#);
+        assert_eq!(exchange["response"]["status"], 200);
+        assert_eq!(exchange["response"]["body"], r#"{"id":"chatcmpl-1"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_stats_count_bytes() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("x".repeat(1000)))
+            .mount(&upstream)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/stream"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/event-stream")
+                    .set_body_string("data: {}\n\n".repeat(10)),
+            )
+            .mount(&upstream)
+            .await;
+        let proxy = spawn_proxy(&upstream.uri()).await;
+
+        let client = reqwest::Client::new();
+        client
+            .post(format!("{}/openai/v1/chat/completions", proxy))
+            .body("y".repeat(250))
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let streamed = reqwest::get(format!("{}/openai/v1/stream", proxy))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(streamed.len(), 100);
+
+        let stats = fetch_stats(&proxy).await;
+        let openai = &stats["services"]["openai"];
+        assert_eq!(openai["bytes_sent"], 250);
+        assert_eq!(openai["bytes_received"], 1100);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_over_unix_socket() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("clawproxy.sock");
+        let mut config = Config::default();
+        config.listen.socket_path = Some(socket_path.clone());
+        let server = tokio::spawn(ProxyServer::new(config, SecretStore::new()).run());
+
+        // Wait for the socket to be bound
+        let mut stream = loop {
+            match tokio::net::UnixStream::connect(&socket_path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        };
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            STATS_PATH
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("\"services\""), "{}", response);
+        server.abort();
+    }
+
+    #[test]
+    fn test_upstream_headers_log_client_auth_override() {
+        let mut incoming = HeaderMap::new();
+        incoming.insert("authorization", HeaderValue::from_static("Bearer sk-client"));
+        let ((), logs) = capture_logs(|| {
+            let headers =
+                build_upstream_headers(&incoming, &test_service(), "Bearer sk-1").unwrap();
+            assert_eq!(headers.get("authorization").unwrap(), "Bearer sk-1");
+        });
+        assert!(logs.contains("Replacing client-supplied auth header"));
+        assert!(!logs.contains("sk-client"));
+
+        // A placeholder is the expected value and isn't an override
+        let mut incoming = HeaderMap::new();
+        incoming.insert("authorization", HeaderValue::from_static("Bearer PROXY:openai"));
+        let ((), logs) = capture_logs(|| {
+            build_upstream_headers(&incoming, &test_service(), "Bearer sk-1").unwrap();
+        });
+        assert!(logs.is_empty());
+    }
+
+    #[test]
+    fn test_upstream_headers_reject_client_auth() {
+        let mut service = test_service();
+        service.reject_client_auth = true;
+
+        let mut incoming = HeaderMap::new();
+        incoming.insert("authorization", HeaderValue::from_static("Bearer sk-client"));
+        let err = build_upstream_headers(&incoming, &service, "Bearer sk-1").unwrap_err();
+        assert!(matches!(err, ProxyError::BadRequest(_)));
+
+        // Placeholders and requests without the header are still accepted
+        incoming.insert("authorization", HeaderValue::from_static("Bearer PROXY:openai"));
+        assert!(build_upstream_headers(&incoming, &service, "Bearer sk-1").is_ok());
+        assert!(build_upstream_headers(&HeaderMap::new(), &service, "Bearer sk-1").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reject_client_auth_returns_400() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let upstream = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&upstream)
+            .await;
+
+        let mut config = Config::default();
+        config.services.insert(
+            "openai".to_string(),
+            ServiceConfig {
+                upstream: upstream.uri(),
+                reject_client_auth: true,
+                ..test_service()
+            },
+        );
+        let proxy = spawn_proxy_with(config).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/openai/v1/chat/completions", proxy))
+            .header("authorization", "Bearer sk-client")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[test]
+    fn test_upstream_headers_inject_when_absent() {
+        let headers =
+            build_upstream_headers(&HeaderMap::new(), &test_service(), "Bearer sk-1").unwrap();
+        assert_eq!(headers.get("authorization").unwrap(), "Bearer sk-1");
+        assert_eq!(headers.len(), 1);
+    }
+}
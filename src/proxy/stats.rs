@@ -0,0 +1,190 @@
+//! Per-service request counters, served as JSON at `/_clawproxy/stats`
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Request counters for every service since startup.
+pub struct Stats {
+    started: Instant,
+    services: RwLock<HashMap<String, Arc<ServiceStats>>>,
+}
+
+/// Labels of the status class buckets, indexed by the status's first digit minus one
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// Counters for one service.
+#[derive(Default)]
+struct ServiceStats {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    total_latency_ms: AtomicU64,
+    last_latency_ms: AtomicU64,
+    /// Responses by status class, see `STATUS_CLASSES`
+    status_classes: [AtomicU64; 5],
+    /// Request body bytes sent upstream
+    bytes_sent: AtomicU64,
+    /// Response body bytes received from upstream, before any translation
+    bytes_received: AtomicU64,
+}
+
+/// A service's byte counters. Cloned into streamed bodies, which finish after
+/// the request has been recorded, to count chunks as they pass.
+#[derive(Clone)]
+pub struct ByteCounters(Arc<ServiceStats>);
+
+impl ByteCounters {
+    pub fn add_sent(&self, bytes: usize) {
+        self.0.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, bytes: usize) {
+        self.0.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            services: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request to `service` that took `latency` to produce a response.
+    /// `status` is the upstream response status, or `None` when the request
+    /// failed without one. Both `None` and 4xx/5xx statuses count as errors.
+    pub fn record(&self, service: &str, latency: Duration, status: Option<u16>) {
+        let stats = self.service(service);
+        let latency_ms = latency.as_millis() as u64;
+        stats.requests.fetch_add(1, Ordering::Relaxed);
+        if status.is_none_or(|status| status >= 400) {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let class = status.and_then(|status| (status / 100).checked_sub(1));
+        if let Some(class) = class.and_then(|class| stats.status_classes.get(class as usize)) {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        stats.total_latency_ms.fetch_add(latency_ms, Ordering::Relaxed);
+        stats.last_latency_ms.store(latency_ms, Ordering::Relaxed);
+    }
+
+    /// Byte counters for `service`.
+    pub fn byte_counters(&self, service: &str) -> ByteCounters {
+        ByteCounters(self.service(service))
+    }
+
+    fn service(&self, name: &str) -> Arc<ServiceStats> {
+        if let Some(stats) = self.services.read().unwrap().get(name) {
+            return stats.clone();
+        }
+        self.services
+            .write()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// The counters as JSON, with an average latency per service.
+    pub fn snapshot(&self) -> Value {
+        let services = self.services.read().unwrap();
+        let services: serde_json::Map<String, Value> = services
+            .iter()
+            .map(|(name, stats)| {
+                let requests = stats.requests.load(Ordering::Relaxed);
+                let total_latency_ms = stats.total_latency_ms.load(Ordering::Relaxed);
+                let avg_latency_ms = total_latency_ms.checked_div(requests).unwrap_or(0);
+                let status: serde_json::Map<String, Value> = STATUS_CLASSES
+                    .iter()
+                    .zip(&stats.status_classes)
+                    .map(|(label, count)| (label.to_string(), json!(count.load(Ordering::Relaxed))))
+                    .collect();
+                let entry = json!({
+                    "requests": requests,
+                    "errors": stats.errors.load(Ordering::Relaxed),
+                    "avg_latency_ms": avg_latency_ms,
+                    "last_latency_ms": stats.last_latency_ms.load(Ordering::Relaxed),
+                    "status": status,
+                    "bytes_sent": stats.bytes_sent.load(Ordering::Relaxed),
+                    "bytes_received": stats.bytes_received.load(Ordering::Relaxed),
+                });
+                (name.clone(), entry)
+            })
+            .collect();
+
+        json!({
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "services": services,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_snapshot() {
+        let stats = Stats::new();
+        stats.record("openai", Duration::from_millis(100), Some(200));
+        stats.record("openai", Duration::from_millis(300), Some(500));
+        stats.record("anthropic", Duration::from_millis(50), Some(200));
+
+        let snapshot = stats.snapshot();
+        let openai = &snapshot["services"]["openai"];
+        assert_eq!(openai["requests"], 2);
+        assert_eq!(openai["errors"], 1);
+        assert_eq!(openai["avg_latency_ms"], 200);
+        assert_eq!(openai["last_latency_ms"], 300);
+        assert_eq!(snapshot["services"]["anthropic"]["requests"], 1);
+    }
+
+    #[test]
+    fn test_status_classes() {
+        let stats = Stats::new();
+        for status in [Some(200), Some(201), Some(429), Some(503), None] {
+            stats.record("openai", Duration::ZERO, status);
+        }
+
+        let snapshot = stats.snapshot();
+        let openai = &snapshot["services"]["openai"];
+        assert_eq!(
+            openai["status"],
+            json!({ "1xx": 0, "2xx": 2, "3xx": 0, "4xx": 1, "5xx": 1 })
+        );
+        assert_eq!(openai["requests"], 5);
+        assert_eq!(openai["errors"], 3);
+    }
+
+    #[test]
+    fn test_byte_counters() {
+        let stats = Stats::new();
+        let counters = stats.byte_counters("openai");
+        counters.add_sent(120);
+        counters.add_received(4000);
+        // A streamed body's clone counts into the same service
+        counters.clone().add_received(96);
+
+        let snapshot = stats.snapshot();
+        let openai = &snapshot["services"]["openai"];
+        assert_eq!(openai["bytes_sent"], 120);
+        assert_eq!(openai["bytes_received"], 4096);
+        assert_eq!(openai["requests"], 0);
+    }
+
+    #[test]
+    fn test_empty_snapshot() {
+        let snapshot = Stats::new().snapshot();
+        assert_eq!(snapshot["services"], json!({}));
+        assert!(snapshot["uptime_secs"].is_u64());
+    }
+}
@@ -1,30 +1,52 @@
 //! Token substitution for credential injection
 
 use crate::error::{ProxyError, Result};
-use regex::Regex;
+use regex::{Captures, Regex};
 use std::collections::HashMap;
 use std::sync::LazyLock;
 
-/// Pattern to match PROXY:xxx tokens
-static PROXY_TOKEN_PATTERN: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"PROXY:(\w+)").expect("Invalid regex pattern"));
+/// Pattern to match PROXY:xxx tokens. Names may contain `.`, `-` and `/` between word
+/// characters, so trailing punctuation like `PROXY:openai-prod.` isn't part of the name.
+/// A leading backslash escapes the token: `\PROXY:foo` is passed on as `PROXY:foo`.
+static PROXY_TOKEN_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\\)?PROXY:(\w+(?:[./-]\w+)*)").expect("Invalid regex pattern")
+});
+
+/// A whole secret name, with the same charset as a token's name. Separators only
+/// go between word characters, so names can't hold `..` or start with `/`.
+static SECRET_NAME_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\w+(?:[./-]\w+)*$").expect("Invalid regex pattern"));
+
+/// Whether `name` can be stored as a secret and referred to by a `PROXY:` token
+pub fn is_valid_secret_name(name: &str) -> bool {
+    SECRET_NAME_PATTERN.is_match(name)
+}
 
-/// Substitute PROXY:xxx tokens with actual secrets
+/// Substitute PROXY:xxx tokens with actual secrets in a single pass, so secret
+/// values are never scanned for tokens themselves
 pub fn substitute_tokens(input: &str, secrets: &HashMap<String, String>) -> Result<String> {
-    let mut result = input.to_string();
-
-    for cap in PROXY_TOKEN_PATTERN.captures_iter(input) {
-        let full_match = cap.get(0).unwrap().as_str();
-        let secret_name = cap.get(1).unwrap().as_str();
-
-        let secret_value = secrets
-            .get(secret_name)
-            .ok_or_else(|| ProxyError::InvalidToken(format!("Unknown secret: {}", secret_name)))?;
-
-        result = result.replace(full_match, secret_value);
+    let mut unknown = None;
+
+    let result = PROXY_TOKEN_PATTERN.replace_all(input, |cap: &Captures| {
+        let secret_name = &cap[2];
+        if cap.get(1).is_some() {
+            return format!("PROXY:{}", secret_name);
+        }
+        match secrets.get(secret_name) {
+            Some(secret_value) => secret_value.clone(),
+            None => {
+                unknown.get_or_insert_with(|| secret_name.to_string());
+                String::new()
+            }
+        }
+    });
+
+    match unknown {
+        Some(secret_name) => {
+            Err(ProxyError::InvalidToken(format!("Unknown secret: {}", secret_name)).into())
+        }
+        None => Ok(result.into_owned()),
     }
-
-    Ok(result)
 }
 
 /// Format an auth header value using the configured format and secret
@@ -32,15 +54,27 @@ pub fn format_auth_header(format: &str, secret: &str) -> String {
     format.replace("{secret}", secret)
 }
 
-/// Check if a string contains PROXY:xxx tokens
+/// Check if a string contains PROXY:xxx tokens, ignoring escaped ones
 pub fn contains_proxy_token(input: &str) -> bool {
-    PROXY_TOKEN_PATTERN.is_match(input)
+    PROXY_TOKEN_PATTERN
+        .captures_iter(input)
+        .any(|cap| cap.get(1).is_none())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_is_valid_secret_name() {
+        for name in ["openai", "openai_2", "openai-prod", "org.openai", "team/openai"] {
+            assert!(is_valid_secret_name(name), "{}", name);
+        }
+        for name in ["", "../openai", "/etc/passwd", "team//openai", "a..b", "openai-", "a b"] {
+            assert!(!is_valid_secret_name(name), "{}", name);
+        }
+    }
+
     fn test_secrets() -> HashMap<String, String> {
         let mut secrets = HashMap::new();
         secrets.insert("openai".to_string(), "sk-12345".to_string());
@@ -63,6 +97,19 @@ mod tests {
         assert_eq!(result, "sk-12345 and sk-ant-67890");
     }
 
+    #[test]
+    fn test_secret_values_not_rescanned() {
+        let mut secrets = test_secrets();
+        secrets.insert("nested".to_string(), "PROXY:openai".to_string());
+        secrets.insert("repeated".to_string(), "x-PROXY:repeated".to_string());
+
+        let result = substitute_tokens("PROXY:nested PROXY:openai", &secrets).unwrap();
+        assert_eq!(result, "PROXY:openai sk-12345");
+
+        let result = substitute_tokens("PROXY:repeated,PROXY:repeated", &secrets).unwrap();
+        assert_eq!(result, "x-PROXY:repeated,x-PROXY:repeated");
+    }
+
     #[test]
     fn test_unknown_token_error() {
         let secrets = test_secrets();
@@ -77,6 +124,19 @@ mod tests {
         assert_eq!(result, "Bearer sk-real-key");
     }
 
+    #[test]
+    fn test_escaped_token() {
+        let secrets = test_secrets();
+        let substitute = |input: &str| substitute_tokens(input, &secrets).unwrap();
+        assert_eq!(substitute(r"Use \PROXY:foo as the key"), "Use PROXY:foo as the key");
+        assert_eq!(substitute(r"\PROXY:openai"), "PROXY:openai");
+        assert_eq!(substitute(r"\PROXY:foo and PROXY:openai"), "PROXY:foo and sk-12345");
+
+        assert!(!contains_proxy_token(r"\PROXY:foo"));
+        assert!(contains_proxy_token(r"\PROXY:foo PROXY:openai"));
+        assert!(substitute_tokens("PROXY:foo", &secrets).is_err());
+    }
+
     #[test]
     fn test_format_auth_header() {
         assert_eq!(
@@ -92,5 +152,35 @@ mod tests {
         assert!(contains_proxy_token("PROXY:test"));
         assert!(!contains_proxy_token("Bearer sk-12345"));
         assert!(!contains_proxy_token("no token here"));
+        assert!(contains_proxy_token("Bearer PROXY:org.openai"));
+        assert!(!contains_proxy_token("PROXY:"));
+        assert!(!contains_proxy_token("PROXY:-prod"));
+    }
+
+    #[test]
+    fn test_namespaced_secret_names() {
+        let mut secrets = test_secrets();
+        secrets.insert("org.openai".to_string(), "sk-org".to_string());
+        secrets.insert("openai-prod".to_string(), "sk-prod".to_string());
+        secrets.insert("team/anthropic".to_string(), "sk-team".to_string());
+
+        let substitute = |input: &str| substitute_tokens(input, &secrets).unwrap();
+        assert_eq!(substitute("Bearer PROXY:org.openai"), "Bearer sk-org");
+        assert_eq!(substitute("Bearer PROXY:openai-prod"), "Bearer sk-prod");
+        assert_eq!(substitute("PROXY:team/anthropic"), "sk-team");
+        // A name that prefixes another is only replaced where it's the whole name
+        assert_eq!(substitute("PROXY:openai,PROXY:openai-prod"), "sk-12345,sk-prod");
+    }
+
+    #[test]
+    fn test_token_ends_at_trailing_punctuation() {
+        let mut secrets = test_secrets();
+        secrets.insert("openai-prod".to_string(), "sk-prod".to_string());
+
+        let substitute = |input: &str| substitute_tokens(input, &secrets).unwrap();
+        assert_eq!(substitute("key=PROXY:openai-prod."), "key=sk-prod.");
+        assert_eq!(substitute("(PROXY:openai-prod)"), "(sk-prod)");
+        assert_eq!(substitute("PROXY:openai-, PROXY:openai/"), "sk-12345-, sk-12345/");
+        assert_eq!(substitute("PROXY:openai prod"), "sk-12345 prod");
     }
 }
@@ -0,0 +1,453 @@
+//! Built-in request/response body translators between provider API shapes
+//!
+//! `openai_to_anthropic` lets an OpenAI chat completions client talk to the
+//! Anthropic messages API. Only text content is translated; tools and images
+//! are rejected rather than silently dropped.
+
+use crate::config::Transform;
+use crate::error::ProxyError;
+use axum::http::{header, HeaderMap, HeaderValue};
+use serde_json::{json, Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Anthropic API version sent when the client didn't pick one
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires max_tokens; used when the client doesn't set a limit
+const DEFAULT_MAX_TOKENS: u64 = 4096;
+
+/// Point a chat completions URL at the equivalent upstream endpoint.
+pub fn upstream_url(transform: Transform, url: &str) -> String {
+    match transform {
+        Transform::OpenaiToAnthropic => {
+            let Ok(mut parsed) = reqwest::Url::parse(url) else {
+                return url.to_string();
+            };
+            let Some(base) = parsed.path().strip_suffix("/chat/completions") else {
+                return url.to_string();
+            };
+            let path = format!("{}/messages", base);
+            parsed.set_path(&path);
+            parsed.to_string()
+        }
+    }
+}
+
+/// Add headers the upstream API requires. The response body is translated, so
+/// the upstream is asked not to compress it.
+pub fn add_headers(transform: Transform, headers: &mut HeaderMap) {
+    headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+    match transform {
+        Transform::OpenaiToAnthropic => {
+            if !headers.contains_key("anthropic-version") {
+                headers.insert("anthropic-version", HeaderValue::from_static(ANTHROPIC_VERSION));
+            }
+        }
+    }
+}
+
+/// Translate a client request body into the upstream's shape.
+pub fn translate_request(transform: Transform, body: &[u8]) -> Result<Vec<u8>, ProxyError> {
+    let request: Value = serde_json::from_slice(body)
+        .map_err(|e| ProxyError::BadRequest(format!("Request body is not JSON: {}", e)))?;
+    let translated = match transform {
+        Transform::OpenaiToAnthropic => chat_to_messages(&request),
+    }
+    .map_err(ProxyError::BadRequest)?;
+    Ok(translated.to_string().into_bytes())
+}
+
+/// Translate a buffered upstream response body into the client's shape.
+/// Bodies that aren't the expected JSON are passed through unchanged.
+pub fn translate_response(transform: Transform, body: &[u8]) -> Vec<u8> {
+    let Ok(response) = serde_json::from_slice::<Value>(body) else {
+        return body.to_vec();
+    };
+    let translated = match transform {
+        Transform::OpenaiToAnthropic => match response["type"].as_str() {
+            Some("message") => message_to_chat(&response),
+            Some("error") => anthropic_error(&response),
+            _ => return body.to_vec(),
+        },
+    };
+    translated.to_string().into_bytes()
+}
+
+/// Convert an OpenAI chat completions request to an Anthropic messages request.
+fn chat_to_messages(request: &Value) -> Result<Value, String> {
+    let request = request
+        .as_object()
+        .ok_or("Request body must be a JSON object")?;
+    let messages = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("Request must have a messages array")?;
+
+    let mut system = Vec::new();
+    let mut translated = Vec::new();
+    for message in messages {
+        let role = message["role"].as_str().unwrap_or_default();
+        match role {
+            "system" | "developer" => system.push(text_content(&message["content"])?),
+            "user" | "assistant" => translated.push(json!({
+                "role": role,
+                "content": content_blocks(&message["content"])?,
+            })),
+            _ => return Err(format!("Unsupported message role: {}", role)),
+        }
+    }
+
+    let max_tokens = request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .and_then(Value::as_u64)
+        .unwrap_or(DEFAULT_MAX_TOKENS);
+
+    let mut out = Map::new();
+    out.insert("model".to_string(), request.get("model").cloned().unwrap_or(Value::Null));
+    out.insert("messages".to_string(), Value::Array(translated));
+    out.insert("max_tokens".to_string(), json!(max_tokens));
+    if !system.is_empty() {
+        out.insert("system".to_string(), json!(system.join("\n\n")));
+    }
+    for key in ["temperature", "top_p", "stream"] {
+        if let Some(value) = request.get(key) {
+            out.insert(key.to_string(), value.clone());
+        }
+    }
+    match request.get("stop") {
+        Some(Value::String(stop)) => {
+            out.insert("stop_sequences".to_string(), json!([stop]));
+        }
+        Some(Value::Array(stops)) => {
+            out.insert("stop_sequences".to_string(), Value::Array(stops.clone()));
+        }
+        _ => {}
+    }
+    Ok(Value::Object(out))
+}
+
+/// Message content as Anthropic content: a string, or text blocks.
+fn content_blocks(content: &Value) -> Result<Value, String> {
+    match content {
+        Value::String(_) => Ok(content.clone()),
+        Value::Array(parts) => parts
+            .iter()
+            .map(|part| match part["type"].as_str() {
+                Some("text") => Ok(json!({ "type": "text", "text": part["text"] })),
+                other => Err(format!("Unsupported content part: {}", other.unwrap_or("none"))),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        _ => Err("Message content must be a string or an array of parts".to_string()),
+    }
+}
+
+/// Message content flattened to text, for system prompts.
+fn text_content(content: &Value) -> Result<String, String> {
+    match content_blocks(content)? {
+        Value::String(text) => Ok(text),
+        blocks => Ok(blocks
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|block| block["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n")),
+    }
+}
+
+fn finish_reason(stop_reason: &Value) -> Value {
+    match stop_reason.as_str() {
+        Some("end_turn" | "stop_sequence") => json!("stop"),
+        Some("max_tokens") => json!("length"),
+        Some("tool_use") => json!("tool_calls"),
+        Some(other) => json!(other),
+        None => Value::Null,
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Convert an Anthropic message to an OpenAI chat completion.
+fn message_to_chat(message: &Value) -> Value {
+    let text: String = message["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|block| block["text"].as_str())
+        .collect();
+    let input_tokens = message["usage"]["input_tokens"].as_u64().unwrap_or_default();
+    let output_tokens = message["usage"]["output_tokens"].as_u64().unwrap_or_default();
+
+    json!({
+        "id": message["id"],
+        "object": "chat.completion",
+        "created": unix_time(),
+        "model": message["model"],
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": text },
+            "finish_reason": finish_reason(&message["stop_reason"]),
+        }],
+        "usage": {
+            "prompt_tokens": input_tokens,
+            "completion_tokens": output_tokens,
+            "total_tokens": input_tokens + output_tokens,
+        },
+    })
+}
+
+/// Convert an Anthropic error body to the OpenAI error shape.
+fn anthropic_error(error: &Value) -> Value {
+    json!({
+        "error": {
+            "message": error["error"]["message"],
+            "type": error["error"]["type"],
+            "code": Value::Null,
+        }
+    })
+}
+
+/// Translates an upstream Server-Sent Events stream chunk by chunk. Events can
+/// be split across chunks, so incomplete ones are held until the rest arrives.
+pub struct StreamTranslator {
+    transform: Transform,
+    pending: Vec<u8>,
+    id: Value,
+    model: Value,
+    created: u64,
+}
+
+impl StreamTranslator {
+    pub fn new(transform: Transform) -> Self {
+        Self {
+            transform,
+            pending: Vec::new(),
+            id: Value::Null,
+            model: Value::Null,
+            created: unix_time(),
+        }
+    }
+
+    /// Feed a chunk of the upstream stream, returning the translated events it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.pending.extend(chunk.iter().filter(|&&b| b != b'\r'));
+
+        let mut out = Vec::new();
+        while let Some(end) = self.pending.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = self.pending.drain(..end + 2).collect();
+            let data: String = String::from_utf8_lossy(&event)
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(str::trim_start)
+                .collect();
+            if let Ok(data) = serde_json::from_str::<Value>(&data) {
+                match self.transform {
+                    Transform::OpenaiToAnthropic => self.anthropic_event(&data, &mut out),
+                }
+            }
+        }
+        out
+    }
+
+    fn anthropic_event(&mut self, event: &Value, out: &mut Vec<u8>) {
+        match event["type"].as_str() {
+            Some("message_start") => {
+                self.id = event["message"]["id"].clone();
+                self.model = event["message"]["model"].clone();
+                self.chunk(json!({ "role": "assistant", "content": "" }), Value::Null, out);
+            }
+            Some("content_block_delta") if event["delta"]["type"] == "text_delta" => {
+                self.chunk(json!({ "content": event["delta"]["text"] }), Value::Null, out);
+            }
+            Some("message_delta") => {
+                let reason = finish_reason(&event["delta"]["stop_reason"]);
+                self.chunk(json!({}), reason, out);
+            }
+            Some("message_stop") => out.extend_from_slice(b"data: [DONE]\n\n"),
+            Some("error") => write_event(&anthropic_error(event), out),
+            _ => {}
+        }
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Value, out: &mut Vec<u8>) {
+        let chunk = json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        });
+        write_event(&chunk, out);
+    }
+}
+
+fn write_event(data: &Value, out: &mut Vec<u8>) {
+    out.extend_from_slice(b"data: ");
+    out.extend_from_slice(data.to_string().as_bytes());
+    out.extend_from_slice(b"\n\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSFORM: Transform = Transform::OpenaiToAnthropic;
+
+    fn translate(request: Value) -> Value {
+        let body = translate_request(TRANSFORM, request.to_string().as_bytes()).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn test_chat_request_to_messages() {
+        let translated = translate(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "Hi" },
+                { "role": "assistant", "content": "Hello!" },
+                { "role": "user", "content": [{ "type": "text", "text": "What's 2+2?" }] },
+            ],
+            "max_tokens": 100,
+            "temperature": 0.2,
+            "stop": "\n\n",
+            "stream": true,
+        }));
+
+        assert_eq!(
+            translated,
+            json!({
+                "model": "claude-sonnet-4-5",
+                "system": "Be brief.",
+                "messages": [
+                    { "role": "user", "content": "Hi" },
+                    { "role": "assistant", "content": "Hello!" },
+                    { "role": "user", "content": [{ "type": "text", "text": "What's 2+2?" }] },
+                ],
+                "max_tokens": 100,
+                "temperature": 0.2,
+                "stop_sequences": ["\n\n"],
+                "stream": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_request_defaults_and_rejections() {
+        let translated = translate(json!({
+            "model": "claude-sonnet-4-5",
+            "messages": [{ "role": "user", "content": "Hi" }],
+        }));
+        assert_eq!(translated["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert!(translated.get("system").is_none());
+
+        let image = json!({
+            "messages": [{ "role": "user", "content": [{ "type": "image_url" }] }],
+        });
+        assert!(translate_request(TRANSFORM, image.to_string().as_bytes()).is_err());
+        let tool = json!({ "messages": [{ "role": "tool", "content": "42" }] });
+        assert!(translate_request(TRANSFORM, tool.to_string().as_bytes()).is_err());
+        assert!(translate_request(TRANSFORM, b"not json").is_err());
+    }
+
+    #[test]
+    fn test_message_response_to_chat() {
+        let message = json!({
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-5",
+            "content": [{ "type": "text", "text": "4" }],
+            "stop_reason": "end_turn",
+            "usage": { "input_tokens": 12, "output_tokens": 1 },
+        });
+        let body = translate_response(TRANSFORM, message.to_string().as_bytes());
+        let chat: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(chat["object"], "chat.completion");
+        assert_eq!(chat["id"], "msg_01");
+        assert_eq!(chat["choices"][0]["message"]["content"], "4");
+        assert_eq!(chat["choices"][0]["finish_reason"], "stop");
+        assert_eq!(chat["usage"]["total_tokens"], 13);
+
+        let error = json!({
+            "type": "error",
+            "error": { "type": "invalid_request_error", "message": "bad model" },
+        });
+        let body = translate_response(TRANSFORM, error.to_string().as_bytes());
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["message"], "bad model");
+
+        assert_eq!(translate_response(TRANSFORM, b"plain text"), b"plain text");
+    }
+
+    #[test]
+    fn test_stream_translation() {
+        let events = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01\",\"model\":\"claude\"}}\n\n",
+            "event: ping\ndata: {\"type\":\"ping\"}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Hel\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"lo\"}}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"max_tokens\"}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        );
+
+        // Split mid-event to check events are reassembled
+        let mut translator = StreamTranslator::new(TRANSFORM);
+        let (first, second) = events.as_bytes().split_at(100);
+        let mut out = translator.push(first);
+        out.extend(translator.push(second));
+        let out = String::from_utf8(out).unwrap();
+
+        let chunks: Vec<&str> = out
+            .split("\n\n")
+            .filter_map(|event| event.strip_prefix("data: "))
+            .collect();
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks[4], "[DONE]");
+
+        let chunks: Vec<Value> = chunks[..4]
+            .iter()
+            .map(|chunk| serde_json::from_str(chunk).unwrap())
+            .collect();
+        assert_eq!(chunks[0]["id"], "msg_01");
+        assert_eq!(chunks[0]["object"], "chat.completion.chunk");
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hel");
+        assert_eq!(chunks[2]["choices"][0]["delta"]["content"], "lo");
+        assert_eq!(chunks[3]["choices"][0]["finish_reason"], "length");
+    }
+
+    #[test]
+    fn test_upstream_url_and_headers() {
+        assert_eq!(
+            upstream_url(TRANSFORM, "https://api.anthropic.com/v1/chat/completions"),
+            "https://api.anthropic.com/v1/messages"
+        );
+        assert_eq!(
+            upstream_url(TRANSFORM, "https://api.anthropic.com/v1/models"),
+            "https://api.anthropic.com/v1/models"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip, br"));
+        add_headers(TRANSFORM, &mut headers);
+        assert_eq!(headers.get("anthropic-version").unwrap(), ANTHROPIC_VERSION);
+        assert_eq!(headers.get(header::ACCEPT_ENCODING).unwrap(), "identity");
+
+        headers.insert("anthropic-version", HeaderValue::from_static("2024-01-01"));
+        add_headers(TRANSFORM, &mut headers);
+        assert_eq!(headers.get("anthropic-version").unwrap(), "2024-01-01");
+    }
+}
@@ -0,0 +1,227 @@
+//! WebSocket pass-through for services with `websocket: true`
+//!
+//! The proxy answers the client's handshake itself and makes a second one to
+//! the upstream with the injected credential, then relays messages between the
+//! two connections until either side closes.
+
+use axum::body::Body;
+use axum::extract::ws::{self, WebSocket, WebSocketUpgrade};
+use axum::extract::FromRequestParts;
+use axum::http::{header, HeaderMap, Request, Response};
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, protocol::CloseFrame};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::error::ProxyError;
+
+/// Client handshake headers that the upstream handshake generates itself.
+/// Compression isn't supported upstream, so extensions aren't offered.
+const HANDSHAKE_HEADERS: [&str; 3] = [
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-extensions",
+];
+
+type UpstreamSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Whether a request asks to upgrade the connection to a WebSocket.
+pub fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::UPGRADE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.split(',').any(|protocol| protocol.trim().eq_ignore_ascii_case("websocket")))
+}
+
+/// The upstream URL with its scheme switched to `ws` or `wss`.
+fn websocket_url(url: &str) -> Option<url::Url> {
+    let mut url = url::Url::parse(url).ok()?;
+    let scheme = match url.scheme() {
+        "http" => "ws",
+        "https" => "wss",
+        _ => return None,
+    };
+    url.set_scheme(scheme).ok()?;
+    Some(url)
+}
+
+/// Open a WebSocket to `url` with `headers`, then upgrade the client's
+/// connection and relay messages between them.
+///
+/// `headers` are the prepared upstream headers, with the credential injected
+/// and hop-by-hop headers removed. `logged_url` is used in errors, since `url`
+/// may hold a query credential.
+pub async fn proxy(
+    request: Request<Body>,
+    url: &str,
+    logged_url: &str,
+    headers: HeaderMap,
+) -> Result<Response<Body>, ProxyError> {
+    let (mut parts, _body) = request.into_parts();
+    let mut upgrade = WebSocketUpgrade::from_request_parts(&mut parts, &())
+        .await
+        .map_err(|e| {
+            ProxyError::BadRequest(format!("Invalid WebSocket upgrade: {}", e.body_text()))
+        })?;
+
+    let ws_url = websocket_url(url).ok_or_else(|| {
+        ProxyError::InvalidToken(format!("Upstream {} can't carry a WebSocket", logged_url))
+    })?;
+    let mut upstream_request = ws_url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| handshake_error(e, logged_url))?;
+    for (name, value) in headers.iter() {
+        if !HANDSHAKE_HEADERS.contains(&name.as_str()) {
+            upstream_request.headers_mut().append(name, value.clone());
+        }
+    }
+
+    let (upstream, response) = tokio_tungstenite::connect_async(upstream_request)
+        .await
+        .map_err(|e| handshake_error(e, logged_url))?;
+    tracing::info!(upstream = %logged_url, "Upstream WebSocket opened");
+
+    // Agree to the subprotocol the upstream picked, if the client offered it
+    if let Some(protocol) = response
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+    {
+        upgrade = upgrade.protocols([protocol.to_string()]);
+    }
+
+    Ok(upgrade.on_upgrade(move |client| relay(client, upstream)))
+}
+
+/// Map a failed upstream handshake. URL errors are summarized, since their
+/// text can include the URL and its query credential.
+fn handshake_error(err: tungstenite::Error, logged_url: &str) -> ProxyError {
+    match err {
+        tungstenite::Error::Http(response) => ProxyError::UpstreamRequest(format!(
+            "WebSocket handshake to {} was refused with {}",
+            logged_url,
+            response.status()
+        )),
+        tungstenite::Error::Io(e) => {
+            ProxyError::UpstreamUnavailable(format!("WebSocket to {}: {}", logged_url, e))
+        }
+        tungstenite::Error::Url(_) => {
+            ProxyError::InvalidToken(format!("Invalid WebSocket upstream URL {}", logged_url))
+        }
+        e => ProxyError::UpstreamRequest(format!(
+            "WebSocket handshake to {} failed: {}",
+            logged_url, e
+        )),
+    }
+}
+
+/// Relay messages both ways. Once either side closes or fails, both
+/// connections are dropped.
+async fn relay(client: WebSocket, upstream: UpstreamSocket) {
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+
+    let client_to_upstream = async {
+        while let Some(Ok(message)) = client_rx.next().await {
+            let Some(message) = to_upstream(message) else {
+                continue;
+            };
+            let close = message.is_close();
+            if upstream_tx.send(message).await.is_err() || close {
+                break;
+            }
+        }
+    };
+    let upstream_to_client = async {
+        while let Some(Ok(message)) = upstream_rx.next().await {
+            let Some(message) = to_client(message) else {
+                continue;
+            };
+            let close = matches!(message, ws::Message::Close(_));
+            if client_tx.send(message).await.is_err() || close {
+                break;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = client_to_upstream => {}
+        _ = upstream_to_client => {}
+    }
+    tracing::debug!("WebSocket relay closed");
+}
+
+/// Text, binary and close messages are relayed; each side answers its own pings.
+fn to_upstream(message: ws::Message) -> Option<tungstenite::Message> {
+    match message {
+        ws::Message::Text(text) => Some(tungstenite::Message::Text(text)),
+        ws::Message::Binary(data) => Some(tungstenite::Message::Binary(data)),
+        ws::Message::Close(frame) => Some(tungstenite::Message::Close(frame.map(|frame| {
+            CloseFrame {
+                code: frame.code.into(),
+                reason: frame.reason,
+            }
+        }))),
+        ws::Message::Ping(_) | ws::Message::Pong(_) => None,
+    }
+}
+
+fn to_client(message: tungstenite::Message) -> Option<ws::Message> {
+    match message {
+        tungstenite::Message::Text(text) => Some(ws::Message::Text(text)),
+        tungstenite::Message::Binary(data) => Some(ws::Message::Binary(data)),
+        tungstenite::Message::Close(frame) => Some(ws::Message::Close(frame.map(|frame| {
+            ws::CloseFrame {
+                code: frame.code.into(),
+                reason: frame.reason,
+            }
+        }))),
+        tungstenite::Message::Ping(_)
+        | tungstenite::Message::Pong(_)
+        | tungstenite::Message::Frame(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_is_upgrade_request() {
+        let mut headers = HeaderMap::new();
+        assert!(!is_upgrade_request(&headers));
+        headers.insert(header::UPGRADE, HeaderValue::from_static("h2c, WebSocket"));
+        assert!(is_upgrade_request(&headers));
+        headers.insert(header::UPGRADE, HeaderValue::from_static("h2c"));
+        assert!(!is_upgrade_request(&headers));
+    }
+
+    #[test]
+    fn test_websocket_url() {
+        let url = websocket_url("https://api.openai.com/v1/realtime?model=gpt").unwrap();
+        assert_eq!(url.as_str(), "wss://api.openai.com/v1/realtime?model=gpt");
+        let url = websocket_url("http://127.0.0.1:9000/ws").unwrap();
+        assert_eq!(url.as_str(), "ws://127.0.0.1:9000/ws");
+        assert!(websocket_url("ftp://example.com/").is_none());
+    }
+
+    #[test]
+    fn test_close_frame_round_trip() {
+        let close = ws::Message::Close(Some(ws::CloseFrame {
+            code: 1000,
+            reason: "bye".into(),
+        }));
+        let upstream = to_upstream(close).unwrap();
+        assert!(upstream.is_close());
+        let Some(ws::Message::Close(Some(frame))) = to_client(upstream) else {
+            panic!("expected a close frame");
+        };
+        assert_eq!(frame.code, 1000);
+        assert_eq!(frame.reason, "bye");
+
+        assert!(to_upstream(ws::Message::Ping(vec![1])).is_none());
+    }
+}
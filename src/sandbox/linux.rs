@@ -8,6 +8,7 @@ use landlock::{
 };
 use nix::unistd::execvp;
 use std::convert::Infallible;
+use std::collections::BTreeSet;
 use std::ffi::CString;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -31,7 +32,11 @@ impl Sandbox for LinuxSandbox {
             return Err(SandboxError::LandlockNotSupported.into());
         }
 
-        apply_landlock(&sandbox_config.config.secrets_dir)?;
+        apply_landlock(
+            &sandbox_config.config.secrets_dir(),
+            &sandbox_config.deny_read_paths(),
+            &sandbox_config.deny_write_paths(),
+        )?;
         exec(sandbox_config, cmd, args)
     }
 }
@@ -45,13 +50,36 @@ fn is_landlock_available() -> bool {
         .is_ok()
 }
 
-/// Apply Landlock restrictions to deny access to the secrets directory.
+/// The highest Landlock ABI version the running kernel supports, or `None`
+/// if Landlock isn't built in or is disabled.
+pub fn landlock_abi() -> Option<u32> {
+    use nix::libc;
+
+    // Asks for the ABI version instead of creating a ruleset
+    const LANDLOCK_CREATE_RULESET_VERSION: libc::c_uint = 1 << 0;
+
+    // SAFETY: with a null attr, size 0 and the version flag, the kernel only
+    // returns a number and doesn't read or write any memory
+    let abi = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<libc::c_void>(),
+            0usize,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    };
+    u32::try_from(abi).ok().filter(|&abi| abi > 0)
+}
+
+/// Apply Landlock restrictions to deny access to the secrets directory and
+/// the canonical `deny_read` paths, and writes to the `deny_write` paths.
 ///
 /// Landlock is allowlist-based: only paths with explicit rules are accessible.
-/// To deny just the secrets directory, we walk the filesystem tree from root
-/// to secrets_dir and allow all siblings at each level, skipping the path
-/// that leads toward secrets_dir.
-fn apply_landlock(secrets_dir: &Path) -> Result<()> {
+/// To deny just those paths, we walk the filesystem tree from root to each of
+/// them and allow all siblings at each level, skipping the paths that lead
+/// toward a denied one. Read-only paths are walked around the same way and
+/// then allowed for reading only.
+fn apply_landlock(secrets_dir: &Path, deny_read: &[PathBuf], deny_write: &[PathBuf]) -> Result<()> {
     let abi = ABI::V3;
     let access = AccessFs::from_all(abi);
 
@@ -61,44 +89,34 @@ fn apply_landlock(secrets_dir: &Path) -> Result<()> {
         .create()
         .map_err(|e| SandboxError::Apply(e.to_string()))?;
 
-    let denied = fs::canonicalize(secrets_dir)
-        .map_err(|e| SandboxError::Apply(format!("Cannot resolve secrets dir: {}", e)))?;
-
-    // Build chain from root to denied path: ["/", "/home", "/home/user", ..., denied]
-    let mut chain: Vec<PathBuf> = Vec::new();
-    let mut current = denied.as_path();
-    loop {
-        chain.push(current.to_path_buf());
-        match current.parent() {
-            Some(parent) if parent != current => {
-                current = parent;
-            }
-            _ => break,
-        }
+    let mut denied = vec![resolve_denied(secrets_dir)
+        .map_err(|e| SandboxError::Apply(format!("Cannot resolve secrets dir: {}", e)))?];
+    denied.extend_from_slice(deny_read);
+    for path in &denied {
+        tracing::debug!(path = %path.display(), "Denying access");
+    }
+    let read_only = read_only_paths(&denied, deny_write)?;
+    for path in &read_only {
+        tracing::debug!(path = %path.display(), "Denying writes");
     }
-    chain.reverse();
-
-    // For each directory in the chain (except the last, which is denied),
-    // allow access to all its children EXCEPT the next step toward denied
-    for i in 0..chain.len() - 1 {
-        let dir = &chain[i];
-        let excluded_child = &chain[i + 1];
-
-        let entries = fs::read_dir(dir)
-            .map_err(|e| SandboxError::Apply(format!("Cannot read dir {}: {}", dir.display(), e)))?;
 
-        for entry in entries.flatten() {
-            let entry_path = match entry.path().canonicalize() {
-                Ok(p) => p,
-                Err(_) => continue,
-            };
-            if entry_path != *excluded_child {
-                if let Ok(fd) = PathFd::new(&entry_path) {
-                    let _ = ruleset.add_rule(PathBeneath::new(fd, access));
-                }
-            }
+    let walked: Vec<PathBuf> = denied.iter().chain(&read_only).cloned().collect();
+    for path in allowed_paths(Path::new("/"), &walked, list_dir)? {
+        if let Ok(fd) = PathFd::new(&path) {
+            // Through &mut, so a rule that fails doesn't consume the ruleset
+            let _ = (&mut ruleset).add_rule(PathBeneath::new(fd, access));
         }
     }
+    for path in &read_only {
+        // A path that doesn't exist yet gets no rule, so it can't be created either
+        let Ok(fd) = PathFd::new(path) else { continue };
+        let read = if path.is_dir() {
+            AccessFs::from_read(abi)
+        } else {
+            AccessFs::from_read(abi) & AccessFs::from_file(abi)
+        };
+        let _ = (&mut ruleset).add_rule(PathBeneath::new(fd, read));
+    }
 
     let status = ruleset
         .restrict_self()
@@ -119,6 +137,109 @@ fn apply_landlock(secrets_dir: &Path) -> Result<()> {
     Ok(())
 }
 
+/// The `deny_write` paths that need a read-only rule. Those inside a denied
+/// path are already unreadable. One that contains a denied path is refused:
+/// Landlock rules only add access, so reading beneath it would re-expose the
+/// denied path.
+fn read_only_paths(denied: &[PathBuf], deny_write: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut read_only = Vec::new();
+    for path in deny_write {
+        if denied.iter().any(|denied| path.starts_with(denied)) {
+            continue;
+        }
+        if let Some(inner) = denied.iter().find(|denied| denied.starts_with(path)) {
+            let message = format!(
+                "Cannot deny writes to {}: it contains the denied path {}",
+                path.display(),
+                inner.display()
+            );
+            return Err(SandboxError::Apply(message).into());
+        }
+        read_only.push(path.clone());
+    }
+    Ok(read_only)
+}
+
+/// The canonical path to deny. If the secrets dir doesn't exist yet, this is
+/// the first missing component under its deepest existing ancestor, so the
+/// whole path stays denied once it's created.
+fn resolve_denied(secrets_dir: &Path) -> std::io::Result<PathBuf> {
+    match fs::canonicalize(secrets_dir) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        result => return result,
+    }
+
+    let absolute = std::path::absolute(secrets_dir)?;
+    for ancestor in absolute.ancestors().skip(1) {
+        if let Ok(existing) = fs::canonicalize(ancestor) {
+            let missing = absolute.strip_prefix(ancestor).unwrap_or(&absolute);
+            return Ok(existing.join(missing.components().next().unwrap()));
+        }
+    }
+    Ok(absolute)
+}
+
+/// Paths to allow so that everything under `root` except the `denied` paths
+/// stays reachable.
+///
+/// Walks the directories from `root` down to each denied path and allows every
+/// child except the steps toward a denied path. All paths must be canonical.
+/// Children are compared by canonical path, and one that resolves to a denied
+/// path, an ancestor of one or anything inside one is skipped, so a symlink
+/// can't re-expose it.
+///
+/// An unreadable ancestor is skipped with a warning, leaving its other children
+/// inaccessible, but each denied path's parent must be readable.
+fn allowed_paths(
+    root: &Path,
+    denied: &[PathBuf],
+    list_dir: impl Fn(&Path) -> std::io::Result<Vec<PathBuf>>,
+) -> Result<Vec<PathBuf>> {
+    let is_denied = |path: &Path| denied.iter().any(|denied| path.starts_with(denied));
+
+    // Directories leading to a denied path: "/", "/home", "/home/user", ...
+    // Those inside another denied path needn't be walked.
+    let ancestors: BTreeSet<&Path> = denied
+        .iter()
+        .flat_map(|path| path.ancestors().skip(1))
+        .filter(|dir| dir.starts_with(root) && !is_denied(dir))
+        .collect();
+    let parents: BTreeSet<&Path> = denied.iter().filter_map(|path| path.parent()).collect();
+
+    let mut allowed = Vec::new();
+    for dir in ancestors {
+        let entries = match list_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if !parents.contains(dir) => {
+                tracing::warn!(dir = %dir.display(), error = %e, "Skipping unreadable directory");
+                continue;
+            }
+            Err(e) => {
+                let message = format!("Cannot read dir {}: {}", dir.display(), e);
+                return Err(SandboxError::Apply(message).into());
+            }
+        };
+
+        for entry in entries {
+            let entry_path = match entry.canonicalize() {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+            let leads_to_denied = denied.iter().any(|denied| denied.starts_with(&entry_path));
+            if leads_to_denied || is_denied(&entry_path) {
+                continue;
+            }
+            allowed.push(entry_path);
+        }
+    }
+
+    Ok(allowed)
+}
+
+fn list_dir(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    Ok(fs::read_dir(dir)?.flatten().map(|entry| entry.path()).collect())
+}
+
 fn exec(
     sandbox_config: &SandboxConfig,
     cmd: &str,
@@ -154,4 +275,144 @@ mod tests {
         // Verify the check doesn't panic — result depends on kernel version
         let _ = is_landlock_available();
     }
+
+    #[test]
+    fn test_landlock_abi_matches_availability() {
+        // Any kernel with Landlock has at least ABI 1
+        if is_landlock_available() {
+            assert!(landlock_abi().is_some_and(|abi| abi >= 1));
+        }
+    }
+
+    #[test]
+    fn test_read_only_paths() {
+        let denied = vec![PathBuf::from("/home/user/.clawproxy/secrets")];
+        let deny_write = vec![
+            PathBuf::from("/home/user/.bashrc"),
+            PathBuf::from("/home/user/.clawproxy/secrets/openai"),
+        ];
+        assert_eq!(
+            read_only_paths(&denied, &deny_write).unwrap(),
+            vec![PathBuf::from("/home/user/.bashrc")]
+        );
+
+        let err = read_only_paths(&denied, &[PathBuf::from("/home/user")]).unwrap_err();
+        assert!(err.to_string().contains("contains the denied path"));
+    }
+
+    #[test]
+    fn test_allowed_paths_with_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("home/user/.clawproxy/secrets")).unwrap();
+        fs::write(root.join("home/user/.clawproxy/secrets/openai"), "sk-test").unwrap();
+        fs::create_dir_all(root.join("home/user/projects")).unwrap();
+        fs::create_dir(root.join("etc")).unwrap();
+
+        // The configured secrets dir is reached through a symlink
+        symlink(root.join("home/user/.clawproxy"), root.join("home/user/config")).unwrap();
+        // Siblings that resolve to an ancestor, the secrets dir or a secret inside it
+        symlink(root.join("home/user"), root.join("home/user/self")).unwrap();
+        symlink(root.join("home"), root.join("home-link")).unwrap();
+        symlink(root.join("home/user/.clawproxy/secrets"), root.join("etc-secrets")).unwrap();
+        symlink(
+            root.join("home/user/.clawproxy/secrets/openai"),
+            root.join("home/user/.clawproxy/key"),
+        )
+        .unwrap();
+        symlink(root.join("etc"), root.join("home/user/etc-link")).unwrap();
+
+        let denied = root.join("home/user/config/secrets").canonicalize().unwrap();
+        assert_eq!(denied, root.join("home/user/.clawproxy/secrets"));
+
+        let allowed = allowed_paths(&root, std::slice::from_ref(&denied), list_dir).unwrap();
+        for path in &allowed {
+            assert!(!denied.starts_with(path), "{} exposes the secrets", path.display());
+            assert!(!path.starts_with(&denied), "{} is inside the secrets", path.display());
+        }
+        assert!(allowed.contains(&root.join("etc")));
+        assert!(allowed.contains(&root.join("home/user/projects")));
+        assert!(!allowed.contains(&root.join("home/user/.clawproxy/secrets/openai")));
+    }
+
+    #[test]
+    fn test_allowed_paths_skips_unreadable_ancestor() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("restricted/user/secrets")).unwrap();
+        fs::create_dir_all(root.join("restricted/user/projects")).unwrap();
+        fs::create_dir(root.join("etc")).unwrap();
+        let denied = root.join("restricted/user/secrets");
+
+        let unreadable = root.join("restricted");
+        let allowed = allowed_paths(&root, std::slice::from_ref(&denied), |dir| {
+            if dir == unreadable {
+                return Err(std::io::ErrorKind::PermissionDenied.into());
+            }
+            list_dir(dir)
+        })
+        .unwrap();
+        assert!(allowed.contains(&root.join("etc")));
+        assert!(allowed.contains(&root.join("restricted/user/projects")));
+        assert!(!allowed.iter().any(|path| denied.starts_with(path)));
+
+        // The secrets dir's parent can't be skipped
+        let parent = root.join("restricted/user");
+        let result = allowed_paths(&root, std::slice::from_ref(&denied), |dir| {
+            if dir == parent {
+                return Err(std::io::ErrorKind::PermissionDenied.into());
+            }
+            list_dir(dir)
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_allowed_paths_multiple_denied() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        for path in [".clawproxy/secrets", ".ssh", ".aws", ".config/gh", ".config/nvim", "src"] {
+            fs::create_dir_all(root.join("home/user").join(path)).unwrap();
+        }
+        let home = root.join("home/user");
+        let denied = vec![
+            home.join(".clawproxy/secrets"),
+            home.join(".ssh"),
+            home.join(".aws"),
+            home.join(".config/gh"),
+            // Nested in another denied path
+            home.join(".ssh/keys"),
+        ];
+
+        let allowed = allowed_paths(&root, &denied, list_dir).unwrap();
+        for path in &allowed {
+            for denied in &denied {
+                assert!(!denied.starts_with(path), "{} exposes {:?}", path.display(), denied);
+                assert!(!path.starts_with(denied), "{} is inside {:?}", path.display(), denied);
+            }
+        }
+        assert!(allowed.contains(&home.join("src")));
+        assert!(allowed.contains(&home.join(".config/nvim")));
+    }
+
+    #[test]
+    fn test_allowed_paths_missing_secrets_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("home/user/projects")).unwrap();
+
+        let secrets_dir = root.join("home/user/.clawproxy/secrets");
+        let denied = resolve_denied(&secrets_dir).unwrap();
+        assert_eq!(denied, root.join("home/user/.clawproxy"));
+
+        let allowed = allowed_paths(&root, std::slice::from_ref(&denied), list_dir).unwrap();
+        assert!(allowed.contains(&root.join("home/user/projects")));
+        assert!(!allowed.iter().any(|path| secrets_dir.starts_with(path)));
+
+        // Once it exists, the secrets dir itself is denied
+        fs::create_dir_all(&secrets_dir).unwrap();
+        assert_eq!(resolve_denied(&secrets_dir).unwrap(), secrets_dir);
+    }
 }
@@ -7,6 +7,9 @@ use std::convert::Infallible;
 use std::ffi::CString;
 use std::path::{Path, PathBuf};
 
+/// Where macOS ships `sandbox-exec`
+pub const SANDBOX_EXEC_PATH: &str = "/usr/bin/sandbox-exec";
+
 /// macOS sandbox implementation
 pub struct MacOsSandbox;
 
@@ -39,12 +42,32 @@ fn generate_profile(sandbox_config: &SandboxConfig) -> Result<String> {
         return Err(SandboxError::Apply(format!("Profile does not exists at {str_path}")).into());
     }
 
-    let secrets_dir_str = sandbox_config.config.secrets_dir.to_string_lossy();
+    // Deny the resolved path (/var is /private/var); a missing dir is denied as is
+    let secrets_dir = sandbox_config.config.secrets_dir();
+    let secrets_dir = std::fs::canonicalize(&secrets_dir).unwrap_or(secrets_dir);
+    let secrets_dir = quote(&secrets_dir);
     let content = std::fs::read_to_string(&path)?;
-    let profile = content.replace("{secrets_dir}", &secrets_dir_str);
+    // Templates usually quote the placeholder themselves; a bare one is quoted here
+    let mut profile = content
+        .replace("\"{secrets_dir}\"", &secrets_dir)
+        .replace("{secrets_dir}", &secrets_dir);
+
+    // Later rules take precedence, so these override the template's allows
+    for denied in sandbox_config.deny_read_paths() {
+        profile.push_str(&format!("\n(deny file-read* (subpath {}))", quote(&denied)));
+    }
+    for denied in sandbox_config.deny_write_paths() {
+        profile.push_str(&format!("\n(deny file-write* (subpath {}))", quote(&denied)));
+    }
     Ok(profile)
 }
 
+/// A path as a quoted sandbox profile string
+fn quote(path: &Path) -> String {
+    let escaped = path.to_string_lossy().replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
 fn exec(
     profile: &String,
     sandbox_config: &SandboxConfig,
@@ -88,26 +111,118 @@ mod tests {
     use std::fs;
     use tempfile::tempdir;
 
-    #[test]
-    fn test_generate_profile() {
-        let dir: tempfile::TempDir = tempdir().unwrap();
-        let macos_dir = dir.path().join("macos");
+    const ALLOW_ALL: &str = "(version 1)\n(allow default)\n";
+
+    /// A sandbox config whose config dir is `dir`, with `template` as its profile
+    fn sandbox_config(dir: &Path, template: &str) -> SandboxConfig {
+        let macos_dir = dir.join("macos");
         fs::create_dir_all(&macos_dir).unwrap();
-        fs::write(
-            macos_dir.join("sandbox.sb.template"),
-            "(version 1)\n(allow default)\n",
-        )
-        .unwrap();
+        fs::write(macos_dir.join("sandbox.sb.template"), template).unwrap();
 
         let mut config = Config::default();
-        config.location = dir.path().to_path_buf();
-        let sandbox_config = SandboxConfig {
+        config.location = dir.to_path_buf();
+        SandboxConfig {
+            config,
             env: Default::default(),
-            config: config,
-        };
+            deny_read: Vec::new(),
+            deny_write: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_generate_profile() {
+        let dir: tempfile::TempDir = tempdir().unwrap();
+        let sandbox_config = sandbox_config(dir.path(), ALLOW_ALL);
 
         let profile = generate_profile(&sandbox_config).expect("generate_profile failed");
         assert!(profile.contains("(version 1)"));
         assert!(profile.contains("(allow default)"));
     }
+
+    #[test]
+    fn test_generate_profile_deny_read() {
+        let dir: tempfile::TempDir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("home/.ssh")).unwrap();
+        fs::create_dir_all(dir.path().join("home/.aws")).unwrap();
+
+        let mut sandbox_config = sandbox_config(dir.path(), ALLOW_ALL);
+        sandbox_config.config.sandbox.deny_read = vec![format!("{}/home/.*", dir.path().display())];
+
+        let profile = generate_profile(&sandbox_config).expect("generate_profile failed");
+        let home = dir.path().join("home").canonicalize().unwrap();
+        for name in [".ssh", ".aws"] {
+            let rule = format!("(deny file-read* (subpath \"{}\"))", home.join(name).display());
+            assert!(profile.contains(&rule), "missing {}", rule);
+        }
+    }
+
+    #[test]
+    fn test_generate_profile_extra_deny_paths() {
+        let dir: tempfile::TempDir = tempdir().unwrap();
+        let mut sandbox_config = sandbox_config(dir.path(), ALLOW_ALL);
+        sandbox_config.deny_read = vec![PathBuf::from("/Users/me/notes")];
+        sandbox_config.deny_write = vec![
+            PathBuf::from("/Users/me/.bashrc"),
+            PathBuf::from("/Users/me/odd \"name\\dir"),
+        ];
+
+        let profile = generate_profile(&sandbox_config).expect("generate_profile failed");
+        assert!(profile.contains("(deny file-read* (subpath \"/Users/me/notes\"))"));
+        assert!(profile.contains("(deny file-write* (subpath \"/Users/me/.bashrc\"))"));
+        assert!(profile.contains(r#"(deny file-write* (subpath "/Users/me/odd \"name\\dir"))"#));
+    }
+
+    #[test]
+    fn test_generate_profile_missing_secrets_dir() {
+        let dir: tempfile::TempDir = tempdir().unwrap();
+        let template = "(deny file-read* (subpath \"{secrets_dir}\"))";
+        let mut sandbox_config = sandbox_config(dir.path(), template);
+        sandbox_config.config.secrets_dir = dir.path().join("not-created/secrets");
+
+        let profile = generate_profile(&sandbox_config).expect("generate_profile failed");
+        assert!(profile.contains("not-created/secrets"));
+    }
+
+    /// Whether every string is terminated and every paren outside one is balanced
+    fn is_well_formed(profile: &str) -> bool {
+        let (mut depth, mut in_string, mut escaped) = (0i32, false, false);
+        for c in profile.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if in_string => escaped = true,
+                '"' => in_string = !in_string,
+                '(' if !in_string => depth += 1,
+                ')' if !in_string => {
+                    depth -= 1;
+                    if depth < 0 {
+                        return false;
+                    }
+                }
+                _ => {}
+            }
+        }
+        depth == 0 && !in_string
+    }
+
+    #[test]
+    fn test_generate_profile_escapes_secrets_dir() {
+        let dir: tempfile::TempDir = tempdir().unwrap();
+        let template = "(version 1)\n(allow default)\n\
+                        (deny file-read* (subpath \"{secrets_dir}\"))\n\
+                        (deny file-write* (subpath {secrets_dir}))\n";
+        let mut sandbox_config = sandbox_config(dir.path(), template);
+
+        // A space, a quote and a paren that would otherwise end the rule early
+        let secrets_dir = dir.path().join("my \"secrets\") (allow file-read*");
+        fs::create_dir_all(&secrets_dir).unwrap();
+        sandbox_config.config.secrets_dir = secrets_dir.clone();
+
+        let profile = generate_profile(&sandbox_config).expect("generate_profile failed");
+        assert!(is_well_formed(&profile), "malformed profile:\n{}", profile);
+        let quoted = quote(&secrets_dir.canonicalize().unwrap());
+        assert!(quoted.ends_with(r#"/my \"secrets\") (allow file-read*""#));
+        assert!(profile.contains(&format!("(deny file-read* (subpath {}))", quoted)));
+        assert!(profile.contains(&format!("(deny file-write* (subpath {}))", quoted)));
+        assert!(!is_well_formed("(deny file-read* (subpath \"/a\"b\"))"));
+    }
 }
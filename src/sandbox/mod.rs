@@ -6,9 +6,12 @@ pub mod linux;
 pub mod macos;
 
 use crate::config::Config;
-use crate::error::{Result};
-use std::collections::HashMap;
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+use crate::error::SandboxError;
+use crate::error::Result;
+use std::collections::{BTreeSet, HashMap};
 use std::convert::Infallible;
+use std::path::{Path, PathBuf};
 
 /// Configuration for the sandbox
 #[derive(Debug, Clone)]
@@ -16,6 +19,10 @@ pub struct SandboxConfig {
     pub config: Config,
     /// Environment variables to set
     pub env: HashMap<String, String>,
+    /// Paths the command can't read, on top of `sandbox.deny_read`
+    pub deny_read: Vec<PathBuf>,
+    /// Paths the command can read but not modify or create
+    pub deny_write: Vec<PathBuf>,
 }
 
 impl SandboxConfig {
@@ -29,8 +36,72 @@ impl SandboxConfig {
         Self {
             config,
             env,
+            deny_read: Vec::new(),
+            deny_write: Vec::new(),
         }
     }
+
+    /// Paths the command can't read besides the secrets dir: `sandbox.deny_read`
+    /// expanded against the filesystem as it is now, plus `deny_read`.
+    pub fn deny_read_paths(&self) -> Vec<PathBuf> {
+        let mut paths =
+            expand_deny_read(&self.config.sandbox.deny_read, dirs::home_dir().as_deref());
+        paths.extend(self.deny_read.iter().map(PathBuf::as_path).map(resolve_path));
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// The `deny_write` paths, resolved like `deny_read_paths`.
+    pub fn deny_write_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> =
+            self.deny_write.iter().map(PathBuf::as_path).map(resolve_path).collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}
+
+/// The canonical form of `path`, or its absolute form if it doesn't exist yet,
+/// so that a file created later is still covered.
+fn resolve_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Expand `deny_read` paths and globs to the canonical paths they match, with
+/// `~/` standing for `home`. A pattern that matches nothing is skipped with a
+/// warning rather than failing the launch.
+pub fn expand_deny_read(patterns: &[String], home: Option<&Path>) -> Vec<PathBuf> {
+    let mut paths = BTreeSet::new();
+    for pattern in patterns {
+        let expanded = match (pattern.strip_prefix("~/"), home) {
+            (Some(rest), Some(home)) => {
+                format!("{}/{}", glob::Pattern::escape(&home.to_string_lossy()), rest)
+            }
+            (Some(_), None) => {
+                tracing::warn!(%pattern, "No home directory for deny_read pattern, skipping");
+                continue;
+            }
+            (None, _) => pattern.clone(),
+        };
+        let matches: Vec<PathBuf> = match glob::glob(&expanded) {
+            Ok(entries) => entries
+                .flatten()
+                .filter_map(|path| std::fs::canonicalize(path).ok())
+                .collect(),
+            Err(e) => {
+                tracing::warn!(%pattern, error = %e, "Invalid deny_read pattern, skipping");
+                continue;
+            }
+        };
+        if matches.is_empty() {
+            tracing::warn!(%pattern, "deny_read pattern matches nothing");
+        }
+        paths.extend(matches);
+    }
+    paths.into_iter().collect()
 }
 
 /// Trait for platform-specific sandbox implementations
@@ -45,6 +116,86 @@ pub trait Sandbox {
     ) -> Result<Infallible>;
 }
 
+/// Landlock ABI version the Linux sandbox is written against; older kernels
+/// can't handle every access right it restricts
+pub const LANDLOCK_TARGET_ABI: u32 = 3;
+
+/// How completely the sandbox's restrictions can be applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enforcement {
+    /// Every restriction is applied
+    Full,
+    /// Some access rights can't be restricted on this kernel
+    Partial,
+}
+
+/// The sandbox backend available on this machine, as reported by [`probe`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SandboxSupport {
+    /// Landlock, at the highest ABI version the kernel supports
+    Landlock { abi: u32, enforcement: Enforcement },
+    /// macOS `sandbox-exec`
+    SandboxExec,
+    /// No sandbox; commands can only run with `--no-sandbox`
+    Unavailable,
+}
+
+impl SandboxSupport {
+    /// Whether sandboxed commands can run at all
+    pub fn is_available(&self) -> bool {
+        !matches!(self, SandboxSupport::Unavailable)
+    }
+}
+
+impl std::fmt::Display for SandboxSupport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxSupport::Landlock { abi, enforcement: Enforcement::Full } => {
+                write!(f, "Linux (Landlock ABI {}, fully enforced)", abi)
+            }
+            SandboxSupport::Landlock { abi, enforcement: Enforcement::Partial } => write!(
+                f,
+                "Linux (Landlock ABI {}, partially enforced; ABI {} needed for full)",
+                abi, LANDLOCK_TARGET_ABI
+            ),
+            SandboxSupport::SandboxExec => write!(f, "macOS (sandbox-exec)"),
+            SandboxSupport::Unavailable => write!(f, "unavailable"),
+        }
+    }
+}
+
+/// Detect which sandbox this machine can apply, without applying it.
+pub fn probe() -> SandboxSupport {
+    #[cfg(target_os = "linux")]
+    {
+        match linux::landlock_abi() {
+            Some(abi) if abi >= LANDLOCK_TARGET_ABI => SandboxSupport::Landlock {
+                abi,
+                enforcement: Enforcement::Full,
+            },
+            Some(abi) => SandboxSupport::Landlock {
+                abi,
+                enforcement: Enforcement::Partial,
+            },
+            None => SandboxSupport::Unavailable,
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        if Path::new(macos::SANDBOX_EXEC_PATH).exists() {
+            SandboxSupport::SandboxExec
+        } else {
+            SandboxSupport::Unavailable
+        }
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        SandboxSupport::Unavailable
+    }
+}
+
 /// Create the appropriate sandbox for the current platform
 pub fn create_sandbox() -> Result<Box<dyn Sandbox>> {
     #[cfg(target_os = "macos")]
@@ -62,3 +213,91 @@ pub fn create_sandbox() -> Result<Box<dyn Sandbox>> {
         Err(SandboxError::NotAvailable.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_expand_deny_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let home = root.join("home");
+        for path in [".ssh", ".aws", ".config/gh", ".config/nvim", "projects"] {
+            fs::create_dir_all(home.join(path)).unwrap();
+        }
+        fs::write(home.join(".netrc"), "machine example.com").unwrap();
+
+        let patterns = vec![
+            "~/.ssh".to_string(),
+            "~/.config/*".to_string(),
+            format!("{}/.a*", home.display()),
+            "~/.netrc".to_string(),
+            "~/.no-such-dir*".to_string(),
+        ];
+        let denied = expand_deny_read(&patterns, Some(&home));
+        assert_eq!(
+            denied,
+            vec![
+                home.join(".aws"),
+                home.join(".config/gh"),
+                home.join(".config/nvim"),
+                home.join(".netrc"),
+                home.join(".ssh"),
+            ]
+        );
+
+        // Without a home directory, ~/ patterns are skipped
+        assert!(expand_deny_read(&["~/.ssh".to_string()], None).is_empty());
+    }
+
+    #[test]
+    fn test_extra_deny_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join(".ssh")).unwrap();
+
+        let mut sandbox_config = SandboxConfig::for_secrets(Config::default(), "http://proxy");
+        sandbox_config.deny_read = vec![root.join(".ssh"), root.join("./.ssh")];
+        sandbox_config.deny_write = vec![root.join(".bashrc")];
+        assert_eq!(sandbox_config.deny_read_paths(), vec![root.join(".ssh")]);
+        // A file that doesn't exist yet is still denied, so it can't be created
+        assert_eq!(sandbox_config.deny_write_paths(), vec![root.join(".bashrc")]);
+    }
+
+    #[test]
+    fn test_probe() {
+        let support = probe();
+        assert!(!support.to_string().is_empty());
+
+        #[cfg(target_os = "linux")]
+        assert!(matches!(
+            support,
+            SandboxSupport::Landlock { .. } | SandboxSupport::Unavailable
+        ));
+        #[cfg(target_os = "macos")]
+        assert!(matches!(
+            support,
+            SandboxSupport::SandboxExec | SandboxSupport::Unavailable
+        ));
+        #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+        assert_eq!(support, SandboxSupport::Unavailable);
+    }
+
+    #[test]
+    fn test_sandbox_support_display() {
+        let full = SandboxSupport::Landlock {
+            abi: 4,
+            enforcement: Enforcement::Full,
+        };
+        assert_eq!(full.to_string(), "Linux (Landlock ABI 4, fully enforced)");
+        let partial = SandboxSupport::Landlock {
+            abi: 1,
+            enforcement: Enforcement::Partial,
+        };
+        assert!(partial.to_string().contains("partially enforced"));
+        assert!(partial.is_available());
+        assert!(!SandboxSupport::Unavailable.is_available());
+    }
+}
@@ -0,0 +1,84 @@
+//! OpenTelemetry OTLP trace export (enabled with the `otel` feature)
+//!
+//! The exporter is configured through the standard `OTEL_EXPORTER_OTLP_*`
+//! environment variables. Export is only enabled when
+//! `OTEL_EXPORTER_OTLP_ENDPOINT` (or the traces-specific variant) is set.
+
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::trace::{Config as TraceConfig, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Service name reported to the collector
+const SERVICE_NAME: &str = "clawproxy";
+
+/// Returns true if an OTLP endpoint has been configured in the environment.
+pub fn otlp_configured() -> bool {
+    ["OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"]
+        .iter()
+        .any(|var| std::env::var(var).map(|v| !v.is_empty()).unwrap_or(false))
+}
+
+/// Build a batching OTLP tracer. Must be called from within a Tokio runtime.
+pub fn build_otlp_tracer() -> std::result::Result<Tracer, TraceError> {
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(
+            TraceConfig::default()
+                .with_resource(Resource::new(vec![KeyValue::new("service.name", SERVICE_NAME)])),
+        )
+        .install_batch(runtime::Tokio)?;
+
+    // Register globally so shutdown() can flush pending spans on exit
+    opentelemetry::global::set_tracer_provider(provider.clone());
+
+    Ok(provider.tracer(SERVICE_NAME))
+}
+
+/// Build the tracing layer that exports spans over OTLP, if configured.
+///
+/// Returns None when no endpoint is set, when called outside a Tokio runtime
+/// (the batch exporter needs one), or when the exporter fails to build.
+pub fn otlp_layer<S>() -> Option<OpenTelemetryLayer<S, Tracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if !otlp_configured() {
+        return None;
+    }
+
+    if tokio::runtime::Handle::try_current().is_err() {
+        eprintln!("OTLP export requires a Tokio runtime; trace export disabled");
+        return None;
+    }
+
+    match build_otlp_tracer() {
+        Ok(tracer) => Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+        Err(e) => {
+            eprintln!("Failed to initialize OTLP exporter: {}", e);
+            None
+        }
+    }
+}
+
+/// Flush pending spans and shut down the global tracer provider.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_build_otlp_tracer_with_endpoint() {
+        std::env::set_var("OTEL_EXPORTER_OTLP_ENDPOINT", "http://127.0.0.1:4317");
+        assert!(otlp_configured());
+        assert!(build_otlp_tracer().is_ok());
+        shutdown();
+    }
+}
@@ -0,0 +1,46 @@
+//! Log capture shared by unit tests
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use tracing::Subscriber;
+
+/// Log writer that appends to a shared buffer.
+#[derive(Clone, Default)]
+pub struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    /// A debug-level subscriber writing plain lines to this buffer.
+    pub fn subscriber(&self) -> impl Subscriber + Send + Sync {
+        let writer = self.clone();
+        tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish()
+    }
+
+    /// Everything logged so far.
+    pub fn output(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `f` with a debug-level subscriber installed and return its result
+/// with what it logged.
+pub fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let captured = Captured::default();
+    let result = tracing::subscriber::with_default(captured.subscriber(), f);
+    (result, captured.output())
+}
@@ -0,0 +1,65 @@
+//! End-to-end tests that run the built binaries
+
+use assert_cmd::Command;
+use std::fs;
+use tempfile::TempDir;
+
+/// A home directory with a minimal `~/.clawproxy/config.yaml`
+fn home_with_config() -> TempDir {
+    let home = TempDir::new().unwrap();
+    let config_dir = home.path().join(".clawproxy");
+    fs::create_dir(&config_dir).unwrap();
+    fs::write(config_dir.join("config.yaml"), "listen: {}\nservices: {}\n").unwrap();
+    home
+}
+
+#[test]
+fn test_run_exits_with_command_status() {
+    let home = home_with_config();
+    Command::cargo_bin("clawproxy-run")
+        .unwrap()
+        .env("HOME", home.path())
+        .env("CLAWPROXY_CONFIRM_NO_SANDBOX", "1")
+        .args(["--no-sandbox", "-c", "exit 3"])
+        .assert()
+        .code(3);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_timeout_kills_command() {
+    let home = home_with_config();
+    let started = std::time::Instant::now();
+    Command::cargo_bin("clawproxy-run")
+        .unwrap()
+        .env("HOME", home.path())
+        .env("CLAWPROXY_CONFIRM_NO_SANDBOX", "1")
+        .args(["--no-sandbox", "--timeout-secs", "1", "-c", "sleep 30"])
+        .assert()
+        .code(124);
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_timeout_forwards_sigterm() {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    let home = home_with_config();
+    let started = std::time::Instant::now();
+    let mut run = std::process::Command::new(assert_cmd::cargo::cargo_bin("clawproxy-run"))
+        .env("HOME", home.path())
+        .env("CLAWPROXY_CONFIRM_NO_SANDBOX", "1")
+        .args(["--no-sandbox", "--timeout-secs", "30", "-c", "sleep 30"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    kill(Pid::from_raw(run.id() as i32), Signal::SIGTERM).unwrap();
+
+    // The command is terminated, and clawproxy-run reports it
+    assert_eq!(run.wait().unwrap().code(), Some(128 + 15));
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+}